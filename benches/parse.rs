@@ -0,0 +1,62 @@
+//! Compares loading a large PNG file with `fs::read` against loading it through a memory map.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use imagico::png::{chunk::Chunk, chunk_type::ChunkType, mapped::MappedFile, Png, PngRef};
+use std::path::{Path, PathBuf};
+use std::{fs, str::FromStr};
+
+/// Size of the data of each `IDAT` chunk in the generated files
+const IDAT_SIZE: usize = 1 << 20;
+
+/// Writes a PNG file holding `idat_count` chunks of `IDAT_SIZE` bytes each.
+///
+/// The image data is not a valid zlib stream; parsing only looks at the chunk layout.
+fn write_png(path: &Path, idat_count: usize) {
+    let ihdr = Chunk::new(
+        ChunkType::from_str("IHDR").unwrap(),
+        vec![0, 0, 4, 0, 0, 0, 4, 0, 8, 6, 0, 0, 0],
+    );
+    let mut chunks = vec![ihdr];
+    for i in 0..idat_count {
+        let data = (0..IDAT_SIZE).map(|j| (i + j * 31) as u8).collect();
+        chunks.push(Chunk::new(ChunkType::from_str("IDAT").unwrap(), data));
+    }
+    chunks.push(Chunk::new(ChunkType::from_str("IEND").unwrap(), Vec::new()));
+    fs::write(path, Png::from_chunks(chunks).as_bytes()).unwrap();
+}
+
+fn bench_parse(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse");
+    group.sample_size(10);
+
+    for idat_count in [8, 64] {
+        let path: PathBuf = std::env::temp_dir().join(format!(
+            "imagico-bench-{}-{}.png",
+            std::process::id(),
+            idat_count
+        ));
+        write_png(&path, idat_count);
+        let size = fs::metadata(&path).unwrap().len();
+        group.throughput(Throughput::Bytes(size));
+
+        group.bench_with_input(BenchmarkId::new("read", size), &path, |b, path| {
+            b.iter(|| Png::try_from(fs::read(path).unwrap().as_slice()).unwrap())
+        });
+        group.bench_with_input(BenchmarkId::new("mmap_owned", size), &path, |b, path| {
+            b.iter(|| MappedFile::open(path).unwrap().to_png().unwrap())
+        });
+        group.bench_with_input(BenchmarkId::new("mmap_borrowed", size), &path, |b, path| {
+            b.iter(|| {
+                let file = MappedFile::open(path).unwrap();
+                let png = PngRef::try_from(file.as_bytes()).unwrap();
+                png.chunks().len()
+            })
+        });
+
+        fs::remove_file(&path).unwrap();
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_parse);
+criterion_main!(benches);
@@ -0,0 +1,663 @@
+//! Implements steganalysis: checks for signs that a PNG file holds a hidden message.
+//!
+//! Each check produces a `Finding` with a score from `0.0` (nothing suspicious) to
+//! `1.0` (almost certainly a hidden message). The score of a `Report` is the highest
+//! score among its findings.
+
+use crate::{
+    crypto,
+    png::{header::ColorType, palette, pixels::Pixels, segment::Segment, Png},
+};
+use serde::Serialize;
+
+/// Chunk types defined by the PNG specification and its registered extensions
+const STANDARD_CHUNKS: [&[u8; 4]; 29] = [
+    b"IHDR", b"PLTE", b"IDAT", b"IEND", b"acTL", b"bKGD", b"cHRM", b"cICP", b"cLLi", b"eXIf",
+    b"fcTL", b"fdAT", b"gAMA", b"hIST", b"iCCP", b"iTXt", b"mDCv", b"oFFs", b"pCAL", b"pHYs",
+    b"sBIT", b"sCAL", b"sPLT", b"sRGB", b"sTER", b"tEXt", b"tIME", b"tRNS", b"zTXt",
+];
+
+/// Number of pixels in each group of the RS analysis
+const RS_GROUP: usize = 4;
+
+/// The checks run by `analyze`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Check {
+    /// Ancillary chunks that are private or not defined by the PNG specification
+    AncillaryChunks,
+    /// Chunks or bytes stored after the `IEND` chunk
+    DataAfterIend,
+    /// The chi-square attack on pairs of values that differ only in their low bit
+    ChiSquare,
+    /// Regular/singular group analysis of the low bit plane
+    RsAnalysis,
+    /// Palette orders that follow neither color nor frequency of use
+    PaletteOrder,
+}
+
+impl core::fmt::Display for Check {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::AncillaryChunks => "ancillary chunks",
+            Self::DataAfterIend => "data after IEND",
+            Self::ChiSquare => "chi-square",
+            Self::RsAnalysis => "RS analysis",
+            Self::PaletteOrder => "palette order",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// The outcome of a single check
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Finding {
+    /// The check that produced this finding
+    pub check: Check,
+    /// How suspicious the image looks to this check, from 0.0 to 1.0
+    pub score: f64,
+    /// A human-readable explanation of the score
+    pub detail: String,
+}
+
+/// The findings of all checks that apply to an image
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Report {
+    /// The highest score among the findings, from 0.0 to 1.0
+    pub score: f64,
+    /// A one-word summary of the score
+    pub verdict: &'static str,
+    /// The outcome of each check that applies to the image
+    pub findings: Vec<Finding>,
+}
+
+impl Report {
+    fn new(findings: Vec<Finding>) -> Self {
+        let score = findings.iter().map(|f| f.score).fold(0.0, f64::max);
+        let verdict = if score >= 0.7 {
+            "likely"
+        } else if score >= 0.3 {
+            "suspicious"
+        } else {
+            "clean"
+        };
+        Self {
+            score,
+            verdict,
+            findings,
+        }
+    }
+
+    /// Returns this report as pretty-printed JSON
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap_or_default()
+    }
+}
+
+impl core::fmt::Display for Report {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Report {{",)?;
+        writeln!(f, "  Score: {:.2} ({})", self.score, self.verdict)?;
+        for finding in &self.findings {
+            writeln!(
+                f,
+                "  {}: {:.2} - {}",
+                finding.check, finding.score, finding.detail
+            )?;
+        }
+        writeln!(f, "}}",)?;
+        Ok(())
+    }
+}
+
+/// Runs every check that applies to `png` and collects the findings.
+///
+/// The pixel-based checks are skipped when the image data cannot be decoded.
+pub fn analyze(png: &Png) -> Report {
+    let mut findings = vec![ancillary_chunks(png), data_after_iend(png)];
+    if let Ok(pixels) = png.pixels() {
+        if pixels.header().color_type() == ColorType::Indexed {
+            findings.push(palette_order(png, &pixels));
+        } else if pixels.header().bit_depth() == 8 {
+            findings.push(chi_square(&pixels));
+            findings.push(rs_analysis(&pixels));
+        }
+    }
+    Report::new(findings)
+}
+
+fn is_standard(chunk_type: [u8; 4]) -> bool {
+    STANDARD_CHUNKS.iter().any(|t| **t == chunk_type)
+}
+
+/// Flags chunks before `IEND` that are not defined by the PNG specification
+fn ancillary_chunks(png: &Png) -> Finding {
+    let mut score: f64 = 0.0;
+    let mut notes = Vec::new();
+    for chunk in png
+        .chunks()
+        .iter()
+        .take_while(|c| c.chunk_type().bytes() != *b"IEND")
+    {
+        let chunk_type = chunk.chunk_type();
+        if is_standard(chunk_type.bytes()) {
+            continue;
+        }
+        let (chunk_score, note) = if Segment::is_segment(chunk.data()) {
+            (1.0, "holds a message segment")
+        } else if crypto::is_envelope(chunk.data()) {
+            (1.0, "holds an encrypted envelope")
+        } else if !chunk_type.is_public() {
+            (0.8, "private")
+        } else if chunk_type.is_critical() {
+            (0.6, "unknown critical")
+        } else {
+            (0.5, "unknown public")
+        };
+        score = score.max(chunk_score);
+        notes.push(format!(
+            "{} ({}, {} bytes)",
+            chunk_type,
+            note,
+            chunk.length()
+        ));
+    }
+
+    let detail = if notes.is_empty() {
+        "Only standard chunks".to_string()
+    } else {
+        format!("Non-standard chunks: {}", notes.join(", "))
+    };
+    Finding {
+        check: Check::AncillaryChunks,
+        score,
+        detail,
+    }
+}
+
+/// Flags chunks and bytes stored after `IEND`, which decoders never look at
+fn data_after_iend(png: &Png) -> Finding {
+    let after: Vec<_> = png
+        .chunks()
+        .iter()
+        .skip_while(|c| c.chunk_type().bytes() != *b"IEND")
+        .skip(1)
+        .collect();
+    let trailing = png.trailing_data().len();
+    let (score, detail) = if after.is_empty() && trailing == 0 {
+        (0.0, "Nothing after IEND".to_string())
+    } else if after.is_empty() {
+        (1.0, format!("{} bytes after IEND", trailing))
+    } else {
+        let bytes: usize = after.iter().map(|c| c.as_bytes().len()).sum();
+        (
+            1.0,
+            format!(
+                "{} chunks ({} bytes) after IEND",
+                after.len(),
+                bytes + trailing
+            ),
+        )
+    };
+    Finding {
+        check: Check::DataAfterIend,
+        score,
+        detail,
+    }
+}
+
+/// The color samples of `pixels` in the order sequential LSB embedding visits them
+fn color_samples(pixels: &Pixels) -> Vec<Vec<u8>> {
+    let channels = match pixels.header().color_type() {
+        ColorType::Rgb | ColorType::Rgba => 3,
+        _ => 1,
+    };
+    (0..channels)
+        .map(|channel| {
+            (0..pixels.height())
+                .flat_map(|y| (0..pixels.width()).map(move |x| (x, y)))
+                .map(|(x, y)| pixels.sample(x, y, channel) as u8)
+                .collect()
+        })
+        .collect()
+}
+
+/// The chi-square attack of Westfeld and Pfitzmann.
+///
+/// Embedding random bits in the low bit plane evens out the counts of each pair of values
+/// `2k` and `2k + 1`. The score is the probability that the counts are that even by chance,
+/// taken over growing prefixes of the image since sequential embedding fills it from the top.
+fn chi_square(pixels: &Pixels) -> Finding {
+    let channels = color_samples(pixels);
+    let len = channels[0].len() * channels.len();
+    let samples: Vec<u8> = (0..len)
+        .map(|i| channels[i % channels.len()][i / channels.len()])
+        .collect();
+
+    let mut best = (0.0, 0);
+    for percent in [10, 25, 50, 100] {
+        let prefix = &samples[..samples.len() * percent / 100];
+        let p = chi_square_p(prefix);
+        if p > best.0 {
+            best = (p, percent);
+        }
+    }
+    let detail = if best.0 > 0.0 {
+        format!(
+            "Value pairs are even with probability {:.3} in the first {}% of samples",
+            best.0, best.1
+        )
+    } else {
+        "Value pairs are uneven, as in unmodified images".to_string()
+    };
+    Finding {
+        check: Check::ChiSquare,
+        score: best.0,
+        detail,
+    }
+}
+
+/// The probability that the pairs of values in `samples` are as even as observed
+fn chi_square_p(samples: &[u8]) -> f64 {
+    let mut histogram = [0u64; 256];
+    for sample in samples {
+        histogram[*sample as usize] += 1;
+    }
+
+    let mut chi = 0.0;
+    let mut categories = 0;
+    for pair in histogram.chunks_exact(2) {
+        let expected = (pair[0] + pair[1]) as f64 / 2.0;
+        // Pairs seen only once say nothing about their balance
+        if expected >= 1.0 {
+            chi += (pair[0] as f64 - expected).powi(2) / expected;
+            categories += 1;
+        }
+    }
+    if categories < 2 {
+        return 0.0;
+    }
+    gamma_q((categories - 1) as f64 / 2.0, chi / 2.0)
+}
+
+/// The RS steganalysis of Fridrich, Goljan and Du.
+///
+/// Flipping low bits changes the smoothness of small pixel groups in a way that depends on
+/// how many low bits are already random. Comparing the groups made more and less smooth by
+/// flipping, before and after inverting the whole low bit plane, estimates the fraction of
+/// samples that carry payload bits.
+fn rs_analysis(pixels: &Pixels) -> Finding {
+    let estimates: Vec<f64> = color_samples(pixels)
+        .iter()
+        .filter_map(|samples| rs_estimate(samples, pixels.width() as usize))
+        .collect();
+    if estimates.is_empty() {
+        return Finding {
+            check: Check::RsAnalysis,
+            score: 0.0,
+            detail: "Image too small to analyze".to_string(),
+        };
+    }
+    let rate = estimates.iter().sum::<f64>() / estimates.len() as f64;
+    // Unmodified images typically estimate a few percent
+    let score = ((rate - 0.05) / 0.25).clamp(0.0, 1.0);
+    Finding {
+        check: Check::RsAnalysis,
+        score,
+        detail: format!(
+            "An estimated {:.1}% of samples carry embedded bits",
+            rate * 100.0
+        ),
+    }
+}
+
+/// Estimates the fraction of samples with embedded low bits in one channel of an image
+/// `width` samples wide, or `None` if there are no complete groups.
+fn rs_estimate(samples: &[u8], width: usize) -> Option<f64> {
+    let groups: Vec<[i32; RS_GROUP]> = samples
+        .chunks_exact(width)
+        .flat_map(|row| row.chunks_exact(RS_GROUP))
+        .map(|g| [g[0] as i32, g[1] as i32, g[2] as i32, g[3] as i32])
+        .collect();
+    if groups.is_empty() {
+        return None;
+    }
+
+    let d0 = regular_minus_singular(&groups, false, 1);
+    let dn0 = regular_minus_singular(&groups, false, -1);
+    let d1 = regular_minus_singular(&groups, true, 1);
+    let dn1 = regular_minus_singular(&groups, true, -1);
+
+    let a = 2.0 * (d1 + d0);
+    let b = dn0 - dn1 - d1 - 3.0 * d0;
+    let c = d0 - dn0;
+    let z = if a.abs() < f64::EPSILON {
+        if b.abs() < f64::EPSILON {
+            return Some(0.0);
+        }
+        -c / b
+    } else {
+        let discriminant = b * b - 4.0 * a * c;
+        if discriminant < 0.0 {
+            return Some(0.0);
+        }
+        let roots = [
+            (-b + discriminant.sqrt()) / (2.0 * a),
+            (-b - discriminant.sqrt()) / (2.0 * a),
+        ];
+        if roots[0].abs() < roots[1].abs() {
+            roots[0]
+        } else {
+            roots[1]
+        }
+    };
+    Some((z / (z - 0.5)).clamp(0.0, 1.0))
+}
+
+/// The fraction of regular groups minus the fraction of singular groups under the mask
+/// `[0, m, m, 0]`, optionally after inverting every low bit.
+fn regular_minus_singular(groups: &[[i32; RS_GROUP]], invert: bool, m: i32) -> f64 {
+    let smoothness =
+        |g: &[i32; RS_GROUP]| -> i32 { g.windows(2).map(|w| (w[1] - w[0]).abs()).sum() };
+    // F1 swaps 2k and 2k + 1; F-1 swaps 2k - 1 and 2k
+    let flip = |x: i32, m: i32| -> i32 {
+        match m {
+            1 => x ^ 1,
+            -1 => ((x + 1) ^ 1) - 1,
+            _ => x,
+        }
+    };
+    let mask = [0, m, m, 0];
+
+    let (mut regular, mut singular) = (0usize, 0usize);
+    for group in groups {
+        let group = if invert { group.map(|x| x ^ 1) } else { *group };
+        let mut flipped = group;
+        for (x, m) in flipped.iter_mut().zip(mask) {
+            *x = flip(*x, m);
+        }
+        match smoothness(&flipped).cmp(&smoothness(&group)) {
+            core::cmp::Ordering::Greater => regular += 1,
+            core::cmp::Ordering::Less => singular += 1,
+            core::cmp::Ordering::Equal => {}
+        }
+    }
+    (regular as f64 - singular as f64) / groups.len() as f64
+}
+
+/// Flags palettes that hold an imagico payload or whose order follows neither
+/// color similarity nor frequency of use, as image encoders usually produce.
+fn palette_order(png: &Png, pixels: &Pixels) -> Finding {
+    let finding = |score: f64, detail: String| Finding {
+        check: Check::PaletteOrder,
+        score,
+        detail,
+    };
+    if let Ok(payload) = palette::extract(png) {
+        return finding(
+            1.0,
+            format!("Palette order encodes a {}-byte payload", payload.len()),
+        );
+    }
+    let palette = match pixels.palette() {
+        Some(palette) if palette.len() >= 8 => palette,
+        _ => return finding(0.0, "Palette too small to judge its order".to_string()),
+    };
+
+    let distance = |a: &[u8; 3], b: &[u8; 3]| -> f64 {
+        a.iter()
+            .zip(b)
+            .map(|(a, b)| (*a as f64 - *b as f64).powi(2))
+            .sum::<f64>()
+            .sqrt()
+    };
+    let adjacent = palette
+        .windows(2)
+        .map(|w| distance(&w[0], &w[1]))
+        .sum::<f64>()
+        / (palette.len() - 1) as f64;
+    let mut all = 0.0;
+    for (i, a) in palette.iter().enumerate() {
+        for b in &palette[i + 1..] {
+            all += distance(a, b);
+        }
+    }
+    let all = all / (palette.len() * (palette.len() - 1) / 2) as f64;
+    let coherence = if all > 0.0 { adjacent / all } else { 0.0 };
+
+    let mut counts = vec![0usize; palette.len()];
+    for y in 0..pixels.height() {
+        for x in 0..pixels.width() {
+            if let Some(count) = counts.get_mut(pixels.sample(x, y, 0) as usize) {
+                *count += 1;
+            }
+        }
+    }
+    let rises = counts.windows(2).filter(|w| w[0] < w[1]).count();
+    let frequency_sorted = rises * 10 <= counts.len();
+
+    if coherence > 0.85 && !frequency_sorted {
+        finding(
+            0.4,
+            format!(
+                "Neighbouring entries are {:.0}% as far apart as random pairs, and not sorted by use",
+                coherence * 100.0
+            ),
+        )
+    } else {
+        finding(0.0, "Palette is ordered by color or by use".to_string())
+    }
+}
+
+/// The regularized upper incomplete gamma function Q(a, x)
+fn gamma_q(a: f64, x: f64) -> f64 {
+    if x <= 0.0 {
+        return 1.0;
+    }
+    let ln_prefix = a * x.ln() - x - ln_gamma(a);
+    if x < a + 1.0 {
+        // Series expansion of P(a, x)
+        let (mut sum, mut term, mut n) = (1.0 / a, 1.0 / a, a);
+        for _ in 0..500 {
+            n += 1.0;
+            term *= x / n;
+            sum += term;
+            if term.abs() < sum.abs() * 1e-12 {
+                break;
+            }
+        }
+        (1.0 - sum * ln_prefix.exp()).clamp(0.0, 1.0)
+    } else {
+        // Continued fraction for Q(a, x), evaluated with the modified Lentz method
+        let tiny = 1e-300;
+        let mut b = x + 1.0 - a;
+        let mut c = 1.0 / tiny;
+        let mut d = 1.0 / b;
+        let mut h = d;
+        for i in 1..500 {
+            let an = -(i as f64) * (i as f64 - a);
+            b += 2.0;
+            d = an * d + b;
+            if d.abs() < tiny {
+                d = tiny;
+            }
+            c = b + an / c;
+            if c.abs() < tiny {
+                c = tiny;
+            }
+            d = 1.0 / d;
+            let delta = d * c;
+            h *= delta;
+            if (delta - 1.0).abs() < 1e-12 {
+                break;
+            }
+        }
+        (ln_prefix.exp() * h).clamp(0.0, 1.0)
+    }
+}
+
+/// The natural logarithm of the gamma function, by the Lanczos approximation
+fn ln_gamma(x: f64) -> f64 {
+    const COEFFICIENTS: [f64; 6] = [
+        76.180_091_729_471_46,
+        -86.505_320_329_416_77,
+        24.014_098_240_830_91,
+        -1.231_739_572_450_155,
+        0.001_208_650_973_866_179,
+        -0.000_005_395_239_384_953,
+    ];
+    let tmp = x + 5.5;
+    let tmp = tmp - (x + 0.5) * tmp.ln();
+    let series = COEFFICIENTS
+        .iter()
+        .enumerate()
+        .fold(1.000_000_000_190_015, |sum, (i, c)| {
+            sum + c / (x + 1.0 + i as f64)
+        });
+    -tmp + (2.506_628_274_631_000_5 * series / x).ln()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::png::{
+        chunk::Chunk,
+        chunk_type::ChunkType,
+        header::ImageHeader,
+        lsb::{self, LsbOptions},
+        ChunkPosition,
+    };
+    use crate::utils::DICE_PNG;
+    use core::str::FromStr;
+    use std::convert::TryFrom;
+
+    /// A smooth 64x64 RGB gradient whose stretched levels leave uneven value pairs,
+    /// like a photograph after contrast adjustment
+    fn smooth_pixels() -> Pixels {
+        let header: Vec<u8> = [0, 0, 0, 64, 0, 0, 0, 64, 8, 2, 0, 0, 0].to_vec();
+        let header = ImageHeader::try_from(header.as_slice()).unwrap();
+        let data = (0..64 * 64 * 3)
+            .map(|i| {
+                let (x, y, c) = ((i / 3) % 64, (i / 3) / 64, i % 3);
+                (40 + (x + y) * 3 / 2 + c * 30) as u8
+            })
+            .collect();
+        Pixels::from_raw(header, None, data).unwrap()
+    }
+
+    /// Fills every low bit of `pixels` with pseudo-random payload bits
+    fn embed_everywhere(pixels: &mut Pixels) {
+        let capacity = lsb::capacity(pixels.header(), &LsbOptions::default()).unwrap();
+        let mut seed = 99u32;
+        let payload: Vec<u8> = (0..capacity)
+            .map(|_| {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                (seed >> 16) as u8
+            })
+            .collect();
+        lsb::embed(pixels, &payload, &LsbOptions::default()).unwrap();
+    }
+
+    #[test]
+    fn test_clean_image() {
+        let report = analyze(&Png::try_from(DICE_PNG.as_slice()).unwrap());
+        let ancillary = &report.findings[0];
+        assert_eq!(ancillary.check, Check::AncillaryChunks);
+        // The dice image carries one private chunk
+        assert!(ancillary.detail.contains("RuSt"));
+        assert_eq!(report.findings[1].score, 0.0);
+    }
+
+    #[test]
+    fn test_segment_chunk() {
+        let mut png = Png::try_from(DICE_PNG.as_slice()).unwrap();
+        let chunk_type = ChunkType::from_str("raNd").unwrap();
+        let _ = png
+            .insert_payload(chunk_type, b"secret", 4, ChunkPosition::BeforeIend)
+            .unwrap();
+        let report = analyze(&png);
+        assert_eq!(report.findings[0].score, 1.0);
+        assert_eq!(report.verdict, "likely");
+    }
+
+    #[test]
+    fn test_data_after_iend() {
+        let mut png = Png::try_from(DICE_PNG.as_slice()).unwrap();
+        png.append_chunk(Chunk::new(
+            ChunkType::from_str("teXt").unwrap(),
+            b"hidden".to_vec(),
+        ));
+        let finding = data_after_iend(&png);
+        assert_eq!(finding.score, 1.0);
+        assert_eq!(finding.detail, "1 chunks (18 bytes) after IEND");
+
+        let mut png = Png::try_from(DICE_PNG.as_slice()).unwrap();
+        png.set_trailing_data(b"hidden".to_vec());
+        let finding = data_after_iend(&png);
+        assert_eq!(finding.score, 1.0);
+        assert_eq!(finding.detail, "6 bytes after IEND");
+    }
+
+    #[test]
+    fn test_chi_square() {
+        let mut pixels = smooth_pixels();
+        assert!(chi_square(&pixels).score < 0.1);
+        embed_everywhere(&mut pixels);
+        assert!(chi_square(&pixels).score > 0.9);
+    }
+
+    #[test]
+    fn test_rs_analysis() {
+        let mut pixels = smooth_pixels();
+        assert!(rs_analysis(&pixels).score < 0.3);
+        embed_everywhere(&mut pixels);
+        assert!(rs_analysis(&pixels).score > 0.7);
+    }
+
+    #[test]
+    fn test_gamma_q() {
+        // Q(1, x) = e^-x
+        assert!((gamma_q(1.0, 2.0) - (-2.0f64).exp()).abs() < 1e-9);
+        assert!((gamma_q(1.0, 0.5) - (-0.5f64).exp()).abs() < 1e-9);
+        // Q(a, 0) = 1
+        assert_eq!(gamma_q(3.0, 0.0), 1.0);
+    }
+
+    #[test]
+    fn test_report_json() {
+        let report = analyze(&Png::try_from(DICE_PNG.as_slice()).unwrap());
+        let json: serde_json::Value = serde_json::from_str(&report.to_json()).unwrap();
+        assert_eq!(json["findings"][0]["check"], "ancillary-chunks");
+        assert_eq!(json["verdict"], report.verdict);
+    }
+
+    /// A tiny file whose header claims `width`x`height` RGBA pixels of `bit_depth` bits
+    fn oversized_png(width: u32, height: u32, bit_depth: u8) -> Png {
+        let chunk = |chunk_type: &str, data: Vec<u8>| {
+            Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data)
+        };
+        let ihdr = [width.to_be_bytes(), height.to_be_bytes()].concat();
+        // An empty zlib stream
+        let idat = vec![0x78, 0x9c, 0x03, 0x00, 0x00, 0x00, 0x00, 0x01];
+        Png::from_chunks(vec![
+            chunk("IHDR", [ihdr, vec![bit_depth, 6, 0, 0, 0]].concat()),
+            chunk("IDAT", idat),
+            chunk("IEND", Vec::new()),
+        ])
+    }
+
+    #[test]
+    fn test_oversized_header() {
+        // 2147483647x2147483647 16-bit RGBA overflows the image size; 100000x100000
+        // RGBA8 fits but would take 40 GB. Neither may panic or abort.
+        for png in [
+            oversized_png(0x7fff_ffff, 0x7fff_ffff, 16),
+            oversized_png(100_000, 100_000, 8),
+        ] {
+            assert!(png.pixels().is_err());
+            let report = analyze(&png);
+            assert_eq!(report.findings.len(), 2);
+            assert_eq!(report.score, 0.0);
+        }
+    }
+}
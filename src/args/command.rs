@@ -1,56 +1,524 @@
-use super::{DecodeArgs, EncodeArgs, PrintArgs, RemoveArgs};
+use super::{
+    AnalyzeArgs, CapacityArgs, DecodeArgs, EmbedMethod, EncodeArgs, KeyArgs, PrintArgs, RemoveArgs,
+    RepairArgs, TextAction, TextArgs, ValidateArgs,
+};
 use crate::{
-    png::{chunk::Chunk, Png},
-    ProgramResult,
+    analysis, crypto,
+    error::{ArgumentError, PayloadLocation, ProgramError},
+    png::{
+        chunk::Chunk,
+        chunk_type::ChunkType,
+        lsb::{self, LsbOptions},
+        mapped::MappedFile,
+        palette, segment,
+        stream::{self, ChunkReader, ChunkWriter},
+        text::{self, CompressedTextChunk, InternationalTextChunk, TextChunk, TextEntry},
+        Method, ParseOptions, Png,
+    },
+    utils, ProgramResult,
 };
-use std::convert::TryFrom;
-use std::fs;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::Path;
 
-/// Encode a message into a PNG file and save the result
+/// Encode a message into a PNG file and save the result.
+///
+/// The message is stored in a chunk, in the low bits of the pixels with `--method lsb`,
+/// in the order of the palette with `--method palette`, or after the `IEND` chunk
+/// with `--method trailing`. With `--scatter` the pixels are visited in an order keyed by the passphrase.
+/// The chunk and trailing methods stream the file one chunk at a time; the others load it whole.
+/// The output file is replaced atomically, and `--backup` keeps the file it replaces as `.bak`.
 pub fn encode(args: EncodeArgs) -> ProgramResult<()> {
-    let infile_bytes = fs::read(&args.infile_path)?;
-    let outfile = args.outfile_path.unwrap_or(args.infile_path);
-
-    let mut png = Png::try_from(infile_bytes.as_slice())?;
-    let chunk = Chunk::new(args.chunk_type, args.message.as_bytes().to_vec());
-    png.append_chunk(chunk);
-    fs::write(outfile, png.as_bytes())?;
-    Ok(())
+    let outfile = args
+        .outfile_path
+        .unwrap_or_else(|| args.infile_path.clone());
+    let encrypt = args.encrypt || args.key.is_given();
+    let secret = if encrypt || args.lsb.scatter {
+        Some(passphrase(&args.key, true)?)
+    } else {
+        None
+    };
+    let message = match &secret {
+        Some(secret) if encrypt => crypto::encrypt(args.message.as_bytes(), secret)?,
+        _ => args.message.into_bytes(),
+    };
+    let png = match args.method {
+        EmbedMethod::Chunk => {
+            let chunk_type = required_chunk_type(args.chunk_type)?;
+            let chunks = match args.segment_size {
+                Some(segment_size) => segment::to_chunks(&chunk_type, &message, segment_size)?.1,
+                None => vec![Chunk::new(chunk_type, message)],
+            };
+            return stream_file(
+                &args.infile_path,
+                &outfile,
+                args.backup,
+                |reader, writer| stream::insert(reader, writer, &chunks, args.position),
+            );
+        }
+        EmbedMethod::Trailing => {
+            // Any previous trailing data is replaced
+            return stream_file(
+                &args.infile_path,
+                &outfile,
+                args.backup,
+                |mut reader, writer| {
+                    for chunk in reader.by_ref() {
+                        writer.write_chunk(&chunk?)?;
+                    }
+                    let _ = writer.write_trailing(message.as_slice())?;
+                    Ok(())
+                },
+            );
+        }
+        EmbedMethod::Lsb => {
+            let mut png = read_png(&args.infile_path)?;
+            let mut pixels = png.pixels()?;
+            if args.deinterlace {
+                pixels.set_interlaced(false);
+            }
+            match &secret {
+                Some(secret) if args.lsb.scatter => {
+                    let key = lsb::ScatterKey::from_passphrase(secret)?;
+                    lsb::embed_scattered(&mut pixels, &message, &args.lsb.options(), &key)?;
+                }
+                _ => lsb::embed(&mut pixels, &message, &args.lsb.options())?,
+            }
+            png.set_pixels(&pixels, Png::DEFAULT_IDAT_SIZE)?;
+            png
+        }
+        EmbedMethod::Palette => {
+            let mut png = read_png(&args.infile_path)?;
+            palette::embed(&mut png, &message)?;
+            png
+        }
+    };
+    utils::write_atomic(&outfile, args.backup, |file| {
+        Ok(file.write_all(&png.as_bytes())?)
+    })
 }
 
-/// Search for a message hidden in a PNG file and print the message if one is found
+/// Search for a message hidden in a PNG file and print the message if one is found.
+///
+/// The message is read from wherever `--method` says it was hidden, reassembled from its
+/// segments and decrypted if need be. It is printed as text when it is valid UTF-8 and in
+/// `--encoding` otherwise, unless `--raw` or `--output` ask for the bytes as-is.
 pub fn decode(args: DecodeArgs) -> ProgramResult<()> {
-    let file_bytes = fs::read(&args.file_path)?;
-    let png = Png::try_from(file_bytes.as_slice())?;
-    let chunk = png.chunk_by_type(&args.chunk_type.to_string());
-    if let Some(val) = chunk {
-        println!("{}", val);
+    let mut secret = None;
+    let mut message = match args.method {
+        EmbedMethod::Chunk => {
+            let file = MappedFile::open(&args.file_path)?;
+            let chunk_type = required_chunk_type(args.chunk_type)?;
+            file.png_ref()?.payload(&chunk_type.to_string())?
+        }
+        EmbedMethod::Lsb if args.lsb.scatter => {
+            let png = read_png(&args.file_path)?;
+            let key =
+                lsb::ScatterKey::from_passphrase(secret.insert(passphrase(&args.key, false)?))?;
+            lsb::extract_scattered(&png.pixels()?, &args.lsb.options(), &key)?
+        }
+        EmbedMethod::Lsb => {
+            lsb::extract(&read_png(&args.file_path)?.pixels()?, &args.lsb.options())?
+        }
+        EmbedMethod::Palette => palette::extract(&read_png(&args.file_path)?)?,
+        EmbedMethod::Trailing => {
+            let file = MappedFile::open(&args.file_path)?;
+            let trailing = file.png_ref()?.trailing_data();
+            if trailing.is_empty() {
+                return Err(ProgramError::PayloadNotFound(PayloadLocation::Trailing));
+            }
+            trailing.to_vec()
+        }
+    };
+    if crypto::is_envelope(&message) {
+        let secret = match secret {
+            Some(secret) => secret,
+            None => passphrase(&args.key, false)?,
+        };
+        message = crypto::decrypt(&message, &secret)?;
+    }
+
+    if let Some(path) = args.output {
+        fs::write(path, &message)?;
+    } else if args.raw {
+        let mut stdout = io::stdout().lock();
+        stdout.write_all(&message)?;
+        stdout.flush()?;
     } else {
-        println!("No hidden message found!");
+        match std::str::from_utf8(&message) {
+            Ok(message) => println!("{}", message),
+            Err(_) => println!("{}", args.encoding.encode(&message)),
+        }
     }
     Ok(())
 }
 
-/// Remove a chunk from a PNG file and save the result
-pub fn remove(args: RemoveArgs) -> ProgramResult<()> {
-    let file_bytes = fs::read(&args.file_path)?;
-    let mut png = Png::try_from(file_bytes.as_slice())?;
-    match png.remove_chunk(&args.chunk_type.to_string()) {
-        Ok(chunk) => {
-            fs::write(&args.file_path, png.as_bytes())?;
-            println!("Removed chunk: {}", chunk);
+/// Reads the passphrase from the command line, a key file or an interactive prompt.
+///
+/// A trailing newline is stripped from key files. When `confirm` is set, the prompt
+/// asks for the passphrase twice and fails if the two entries differ.
+fn passphrase(key: &KeyArgs, confirm: bool) -> ProgramResult<Vec<u8>> {
+    if let Some(passphrase) = &key.passphrase {
+        return Ok(passphrase.as_bytes().to_vec());
+    }
+    if let Some(path) = &key.key_file {
+        let mut bytes = fs::read(path)?;
+        if bytes.ends_with(b"\n") {
+            let _ = bytes.pop();
+            if bytes.ends_with(b"\r") {
+                let _ = bytes.pop();
+            }
         }
-        Err(e) => println!("Error: {}", e),
+        return Ok(bytes);
     }
+
+    let passphrase = rpassword::prompt_password("Passphrase: ")?;
+    if confirm && rpassword::prompt_password("Confirm passphrase: ")? != passphrase {
+        return Err(ProgramError::PassphraseMismatch);
+    }
+    Ok(passphrase.into_bytes())
+}
+
+/// Returns the chunk type the chunk method works on, which the argument parser
+/// only leaves out for the other methods
+fn required_chunk_type(chunk_type: Option<ChunkType>) -> ProgramResult<ChunkType> {
+    chunk_type.ok_or(ProgramError::InvalidArgument(
+        ArgumentError::MissingChunkType,
+    ))
+}
+
+/// Remove a chunk, or with `--trailing` any data after the `IEND` chunk,
+/// from a PNG file and save the result atomically, keeping a `.bak` copy with `--backup`
+pub fn remove(args: RemoveArgs) -> ProgramResult<()> {
+    let path = &args.file_path;
+    let removed = match args.chunk_type {
+        Some(chunk_type) => stream_file(path, path, args.backup, |reader, writer| {
+            stream::remove(reader, writer, &chunk_type.to_string())
+        })
+        .map(|chunk| format!("Removed chunk: {}", chunk)),
+        None => stream_file(path, path, args.backup, |mut reader, writer| {
+            for chunk in reader.by_ref() {
+                writer.write_chunk(&chunk?)?;
+            }
+            match io::copy(&mut reader.into_inner(), &mut io::sink())? {
+                0 => Err(ProgramError::PayloadNotFound(PayloadLocation::Trailing)),
+                length => Ok(format!("Removed {} bytes after the IEND chunk", length)),
+            }
+        }),
+    };
+    println!("{}", removed?);
     Ok(())
 }
 
-/// Print all of the chunks in a PNG file
+/// Print the image header and all of the chunks in a PNG file.
+///
+/// The file is mapped into memory and its chunks are printed without copying their data.
+/// Textual chunks are followed by their keyword and text, decompressed if need be.
 pub fn print(args: PrintArgs) -> ProgramResult<()> {
-    let file_bytes = fs::read(&args.file_path)?;
-    let png = Png::try_from(file_bytes.as_slice())?;
+    let file = MappedFile::open(&args.file_path)?;
+    let png = file.png_ref()?;
+    match png.header_info() {
+        Ok(header) => println!("{}", header),
+        Err(e) => eprintln!("Warning: {}", e),
+    }
     for chunk in png.chunks() {
         println!("{}", chunk);
+        if let Ok(entry) = TextEntry::try_from(chunk) {
+            println!("Text: {}", entry);
+        }
+    }
+    if !png.trailing_data().is_empty() {
+        println!("Trailing data: {} bytes", png.trailing_data().len());
+    }
+    Ok(())
+}
+
+/// Opens a PNG file for reading one chunk at a time.
+fn open_png(path: &Path) -> ProgramResult<ChunkReader<BufReader<File>>> {
+    ChunkReader::new(BufReader::new(File::open(path)?))
+}
+
+/// Loads a whole PNG file, for the methods that need to own or decode every chunk.
+///
+/// The file is mapped rather than read, so the chunk data is copied only once.
+fn read_png(path: &Path) -> ProgramResult<Png> {
+    MappedFile::open(path)?.to_png()
+}
+
+/// Streams a PNG file from `input` to `output` one chunk at a time through `copy`.
+///
+/// The output is written with `utils::write_atomic`, so `input` and `output` may be the
+/// same file and `output` is only replaced once `copy` succeeds.
+fn stream_file<T>(
+    input: &Path,
+    output: &Path,
+    backup: bool,
+    copy: impl FnOnce(
+        ChunkReader<BufReader<File>>,
+        &mut ChunkWriter<BufWriter<&mut File>>,
+    ) -> ProgramResult<T>,
+) -> ProgramResult<T> {
+    let reader = open_png(input)?;
+    utils::write_atomic(output, backup, |file| {
+        let mut writer = ChunkWriter::new(BufWriter::new(file))?;
+        let value = copy(reader, &mut writer)?;
+        let _ = writer.finish()?;
+        Ok(value)
+    })
+}
+
+/// Print the largest message each embedding method can hide in a PNG file
+pub fn capacity(args: CapacityArgs) -> ProgramResult<()> {
+    let png = read_png(&args.file_path)?;
+    let header = png.header_info()?;
+    println!(
+        "{}x{} {}, {}-bit",
+        header.width(),
+        header.height(),
+        header.color_type(),
+        header.bit_depth()
+    );
+
+    let mut methods = vec![("chunk".to_string(), Method::Chunk)];
+    for bits in 1..=4 {
+        let options = LsbOptions {
+            bits_per_channel: bits,
+            channels: args.channels,
+        };
+        methods.push((format!("lsb --bits {}", bits), Method::Lsb(options)));
+    }
+    methods.push(("palette".to_string(), Method::Palette));
+    methods.push(("trailing data".to_string(), Method::TrailingData));
+
+    for (name, method) in methods {
+        match png.capacity(method) {
+            Ok(capacity) => match (capacity.payload, capacity.encrypted()) {
+                (Some(payload), Some(encrypted)) => println!(
+                    "{}: {} bytes, {} bytes encrypted ({} bytes of framing)",
+                    name, payload, encrypted, capacity.framing
+                ),
+                _ => println!("{}: unlimited", name),
+            },
+            Err(e) => println!("{}: unavailable ({})", name, e),
+        }
     }
     Ok(())
 }
+
+/// Print a scored report of the signs of a hidden message in a PNG file
+pub fn analyze(args: AnalyzeArgs) -> ProgramResult<()> {
+    let png = read_png(&args.file_path)?;
+    let report = analysis::analyze(&png);
+    if args.json {
+        println!("{}", report.to_json());
+    } else {
+        print!("{}", report);
+    }
+    Ok(())
+}
+
+/// Print every structural rule of the PNG spec that a PNG file breaks.
+///
+/// Returns `ProgramError::InvalidStructure` when there is any violation, including data
+/// after `IEND`, so that the app exits with `ProgramError::EXIT_INVALID`.
+pub fn validate(args: ValidateArgs) -> ProgramResult<()> {
+    let file = MappedFile::open(&args.file_path)?;
+    let violations = file.png_ref()?.validate();
+    for violation in &violations {
+        println!("error: {}", violation);
+    }
+    match violations.len() {
+        0 => {
+            println!("{}: OK", args.file_path.display());
+            Ok(())
+        }
+        errors => Err(ProgramError::InvalidStructure { errors }),
+    }
+}
+
+/// Load a damaged PNG file leniently, print what is wrong with it, and save it
+/// with the standard signature and recomputed CRCs.
+///
+/// Damage that hides where the next chunk starts, such as truncation, cannot be repaired.
+pub fn repair(args: RepairArgs) -> ProgramResult<()> {
+    let outfile = args
+        .outfile_path
+        .unwrap_or_else(|| args.infile_path.clone());
+    let (mut png, diagnostics) = {
+        let file = MappedFile::open(&args.infile_path)?;
+        Png::parse(file.as_bytes(), ParseOptions::lenient())?
+    };
+    for diagnostic in &diagnostics {
+        println!("Fixing: {}", diagnostic);
+    }
+    match png.repair() {
+        0 => println!("Nothing to repair"),
+        fixes => println!("Repaired {} problems", fixes),
+    }
+    utils::write_atomic(&outfile, false, |file| Ok(file.write_all(&png.as_bytes())?))
+}
+
+/// Get, set, list or delete the `tEXt`, `zTXt` and `iTXt` entries of a PNG file.
+///
+/// `set` writes an `iTXt` chunk when the text is not Latin-1 or a language or translated
+/// keyword is given, a `zTXt` chunk with `--compress`, and a `tEXt` chunk otherwise.
+/// `get` and `delete` return `ProgramError::PayloadNotFound` when no entry has the keyword.
+pub fn text(args: TextArgs) -> ProgramResult<()> {
+    match args.action {
+        TextAction::Get(args) => {
+            let png = read_png(&args.file_path)?;
+            let entry = text::get(&png, &args.keyword).ok_or_else(|| no_text(&args.keyword))?;
+            println!("{}", entry.text());
+        }
+        TextAction::Set(args) => {
+            let latin1 = args.text.chars().all(|c| u32::from(c) < 256);
+            let entry: TextEntry = match (&args.language, &args.translated_keyword) {
+                (None, None) if latin1 && args.compress => {
+                    CompressedTextChunk::new(&args.keyword, &args.text)?.into()
+                }
+                (None, None) if latin1 => TextChunk::new(&args.keyword, &args.text)?.into(),
+                (language, translated_keyword) => InternationalTextChunk::new(
+                    &args.keyword,
+                    args.compress,
+                    language.as_deref().unwrap_or_default(),
+                    translated_keyword.as_deref().unwrap_or_default(),
+                    &args.text,
+                )?
+                .into(),
+            };
+            let mut png = read_png(&args.file_path)?;
+            text::set(&mut png, &entry)?;
+            utils::write_atomic(&args.file_path, false, |file| {
+                Ok(file.write_all(&png.as_bytes())?)
+            })?;
+        }
+        TextAction::List(args) => {
+            for entry in text::list(&read_png(&args.file_path)?) {
+                println!("{}", entry);
+            }
+        }
+        TextAction::Delete(args) => {
+            let mut png = read_png(&args.file_path)?;
+            match text::delete(&mut png, &args.keyword) {
+                0 => return Err(no_text(&args.keyword)),
+                removed => {
+                    utils::write_atomic(&args.file_path, false, |file| {
+                        Ok(file.write_all(&png.as_bytes())?)
+                    })?;
+                    println!("Removed {} {:?} entries", removed, args.keyword);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The error reported when no text is stored under `keyword`
+fn no_text(keyword: &str) -> ProgramError {
+    ProgramError::PayloadNotFound(PayloadLocation::Text {
+        keyword: keyword.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::args::{App, Command};
+    use crate::utils::DICE_PNG;
+    use clap::Parser;
+
+    fn parse(args: &[&str]) -> Command {
+        App::try_parse_from([&["imapp"], args].concat())
+            .unwrap()
+            .command
+            .unwrap()
+    }
+
+    fn run(args: &[&str]) -> ProgramResult<()> {
+        match parse(args) {
+            Command::Encode(args) => encode(args),
+            Command::Decode(args) => decode(args),
+            Command::Remove(args) => remove(args),
+            Command::Validate(args) => validate(args),
+            _ => unreachable!("only the commands under test are run"),
+        }
+    }
+
+    #[test]
+    fn test_decode_output() {
+        let dir = tempfile::tempdir().unwrap();
+        let image = dir.path().join("image.png");
+        let output = dir.path().join("message.bin");
+        fs::write(&image, DICE_PNG).unwrap();
+        let (image, output) = (image.to_str().unwrap(), output.to_str().unwrap());
+
+        run(&["encode", image, "--chunk-type", "ruSt", "hello"]).unwrap();
+        run(&["decode", image, "ruSt", "--output", output]).unwrap();
+        assert_eq!(fs::read(output).unwrap(), b"hello");
+
+        let conflict = [
+            "imapp", "decode", image, "ruSt", "--raw", "--output", output,
+        ];
+        assert!(App::try_parse_from(conflict).is_err());
+        match parse(&["decode", image, "ruSt", "--raw"]) {
+            Command::Decode(args) => assert!(args.raw && args.output.is_none()),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_chunk_type_required_by_chunk_method() {
+        let missing = [
+            ["imapp", "encode", "image.png", "hello"].as_slice(),
+            &["imapp", "encode", "image.png", "hello", "--method", "chunk"],
+            &["imapp", "decode", "image.png"],
+        ];
+        for args in missing {
+            assert!(App::try_parse_from(args).is_err(), "{:?}", args);
+        }
+        match parse(&["encode", "image.png", "hello", "--method", "lsb"]) {
+            Command::Encode(args) => assert!(args.chunk_type.is_none()),
+            _ => unreachable!(),
+        }
+        match parse(&["decode", "image.png", "--method", "palette"]) {
+            Command::Decode(args) => assert!(args.chunk_type.is_none()),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_not_found_exit_codes() {
+        let dir = tempfile::tempdir().unwrap();
+        let image = dir.path().join("image.png");
+        fs::write(&image, DICE_PNG).unwrap();
+        let image = image.to_str().unwrap();
+
+        for args in [
+            ["decode", image, "NoPe"].as_slice(),
+            &["decode", image, "--method", "trailing"],
+            &["remove", image, "NoPe"],
+            &["remove", image, "--trailing"],
+        ] {
+            let e = run(args).unwrap_err();
+            assert_eq!(e.exit_code(), ProgramError::EXIT_NOT_FOUND, "{:?}", args);
+        }
+        assert_eq!(fs::read(image).unwrap(), DICE_PNG);
+    }
+
+    #[test]
+    fn test_validate_exit_code() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("image.png");
+        let mut png = Png::try_from(DICE_PNG.as_slice()).unwrap();
+        // The dice image carries an unknown critical chunk
+        let _ = png.remove_chunk("RuSt").unwrap();
+        fs::write(&path, png.as_bytes()).unwrap();
+        let image = path.to_str().unwrap();
+        run(&["validate", image]).unwrap();
+
+        png.set_trailing_data(b"stray".to_vec());
+        fs::write(&path, png.as_bytes()).unwrap();
+        let e = run(&["validate", image]).unwrap_err();
+        assert_eq!(e.exit_code(), ProgramError::EXIT_INVALID);
+    }
+}
@@ -1,4 +1,11 @@
-use crate::png::chunk_type::ChunkType;
+// The code generated by the clap derives names `clap::Args` and `clap::Subcommand` in full
+#![allow(unused_qualifications)]
+
+use crate::png::{
+    chunk_type::ChunkType,
+    lsb::{Channels, LsbOptions},
+    ChunkPosition,
+};
 use clap::{Args, Parser, Subcommand};
 use core::str::FromStr;
 use std::path::PathBuf;
@@ -32,6 +39,21 @@ pub enum Command {
 
     /// Print all chunks in a PNG file
     Print(PrintArgs),
+
+    /// Show how large a message each method can hide in a PNG file
+    Capacity(CapacityArgs),
+
+    /// Look for signs of a hidden message in a PNG file
+    Analyze(AnalyzeArgs),
+
+    /// Check that the chunks of a PNG file follow the ordering rules of the PNG spec
+    Validate(ValidateArgs),
+
+    /// Rewrite a damaged PNG file with a correct signature and recomputed CRCs
+    Repair(RepairArgs),
+
+    /// Read and edit the textual metadata (Title, Author, Comment, ...) of a PNG file
+    Text(TextArgs),
 }
 
 /// Possible encoding arguments
@@ -40,15 +62,111 @@ pub struct EncodeArgs {
     /// Path to the input PNG file
     pub infile_path: PathBuf,
 
-    /// Chunk type (like "ruSt")
-    #[arg(value_parser = ChunkType::from_str)]
-    pub chunk_type: ChunkType,
+    /// Chunk type (like "ruSt"); required by the chunk method and unused by the others
+    #[arg(
+        short,
+        long,
+        value_parser = ChunkType::from_str,
+        required_unless_present = "method",
+        required_if_eq("method", "chunk")
+    )]
+    pub chunk_type: Option<ChunkType>,
 
     /// Your secret message
     pub message: String,
 
     /// Path to the output PNG file (optional)
     pub outfile_path: Option<PathBuf>,
+
+    /// Where to insert the chunk: before-iend, after-ihdr, before-idat or end
+    #[arg(long, default_value_t = ChunkPosition::default(), value_parser = ChunkPosition::from_str)]
+    pub position: ChunkPosition,
+
+    /// Split the message across several chunks of at most this many bytes each
+    #[arg(long)]
+    pub segment_size: Option<usize>,
+
+    /// Encrypt the message, prompting for a passphrase unless one is given
+    #[arg(long)]
+    pub encrypt: bool,
+
+    /// Passphrase options; giving either one implies `--encrypt`
+    #[command(flatten)]
+    pub key: KeyArgs,
+
+    /// Where to hide the message
+    #[arg(long, value_enum, default_value_t = EmbedMethod::Chunk)]
+    pub method: EmbedMethod,
+
+    /// Options for the lsb method
+    #[command(flatten)]
+    pub lsb: LsbArgs,
+
+    /// Write interlaced images without interlacing when using the lsb method
+    #[arg(long)]
+    pub deinterlace: bool,
+
+    /// Keep a copy of the file being overwritten with a `.bak` extension appended
+    #[arg(long)]
+    pub backup: bool,
+}
+
+/// Ways of hiding a message in a PNG file
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Eq, PartialEq)]
+pub enum EmbedMethod {
+    /// In the data of an ancillary chunk
+    Chunk,
+    /// In the least significant bits of pixel samples
+    Lsb,
+    /// In the order of the palette entries of an indexed image
+    Palette,
+    /// In bytes appended after the IEND chunk
+    Trailing,
+}
+
+/// Options for the LSB embedding method
+#[derive(Args, Debug, Eq, PartialEq)]
+pub struct LsbArgs {
+    /// Number of low bits of each sample used by the lsb method (1-4)
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u8).range(1..=4))]
+    pub bits: u8,
+
+    /// Channels used by the lsb method, as a combination of R, G, B and A
+    #[arg(long, default_value = "rgb", value_parser = Channels::from_str)]
+    pub channels: Channels,
+
+    /// Spread the message over the image in an order keyed by the passphrase
+    #[arg(long)]
+    pub scatter: bool,
+}
+
+impl LsbArgs {
+    /// Returns these arguments as `LsbOptions`
+    pub fn options(&self) -> LsbOptions {
+        LsbOptions {
+            bits_per_channel: self.bits,
+            channels: self.channels,
+        }
+    }
+}
+
+/// Ways of supplying the passphrase for encrypted messages
+#[derive(Args, Debug, Eq, PartialEq)]
+pub struct KeyArgs {
+    /// Passphrase for the encrypted message
+    #[arg(long, conflicts_with = "key_file")]
+    pub passphrase: Option<String>,
+
+    /// Read the passphrase from this file
+    #[arg(long)]
+    pub key_file: Option<PathBuf>,
+}
+
+impl KeyArgs {
+    /// Returns true if a passphrase was given on the command line or through a file
+    pub fn is_given(&self) -> bool {
+        self.passphrase.is_some() || self.key_file.is_some()
+    }
 }
 
 /// Required arguments for a decode command
@@ -57,9 +175,59 @@ pub struct DecodeArgs {
     /// Path to the PNG file
     pub file_path: PathBuf,
 
-    /// Chunk type (like "ruSt")
-    #[arg(value_parser = ChunkType::from_str)]
-    pub chunk_type: ChunkType,
+    /// Chunk type (like "ruSt"); only used by the chunk method
+    #[arg(
+        value_parser = ChunkType::from_str,
+        required_unless_present = "method",
+        required_if_eq("method", "chunk")
+    )]
+    pub chunk_type: Option<ChunkType>,
+
+    /// Write the message bytes to stdout as-is
+    #[arg(long, conflicts_with = "output")]
+    pub raw: bool,
+
+    /// Write the message bytes to this file instead of stdout
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+
+    /// How to print messages that are not valid UTF-8
+    #[arg(long, value_enum, default_value_t = BinaryEncoding::Hex)]
+    pub encoding: BinaryEncoding,
+
+    /// Passphrase options for encrypted messages; prompts when neither is given
+    #[command(flatten)]
+    pub key: KeyArgs,
+
+    /// Where the message is hidden
+    #[arg(long, value_enum, default_value_t = EmbedMethod::Chunk)]
+    pub method: EmbedMethod,
+
+    /// Options for the lsb method
+    #[command(flatten)]
+    pub lsb: LsbArgs,
+}
+
+/// Text encodings for printing binary messages
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Eq, PartialEq)]
+pub enum BinaryEncoding {
+    /// Lowercase hexadecimal
+    Hex,
+    /// Standard base64 with padding
+    Base64,
+}
+
+impl BinaryEncoding {
+    /// Encodes `bytes` as printable text
+    pub fn encode(&self, bytes: &[u8]) -> String {
+        match self {
+            Self::Hex => hex::encode(bytes),
+            Self::Base64 => {
+                use base64::Engine;
+                base64::engine::general_purpose::STANDARD.encode(bytes)
+            }
+        }
+    }
 }
 
 /// Required arguments for a remove command
@@ -69,8 +237,16 @@ pub struct RemoveArgs {
     pub file_path: PathBuf,
 
     /// Chunk type (like "ruSt")
-    #[arg(value_parser = ChunkType::from_str)]
-    pub chunk_type: ChunkType,
+    #[arg(value_parser = ChunkType::from_str, required_unless_present = "trailing")]
+    pub chunk_type: Option<ChunkType>,
+
+    /// Strip any bytes after the IEND chunk instead of removing a chunk
+    #[arg(long, conflicts_with = "chunk_type")]
+    pub trailing: bool,
+
+    /// Keep a copy of the original file with a `.bak` extension appended
+    #[arg(long)]
+    pub backup: bool,
 }
 
 /// Required arguments for a print command
@@ -80,6 +256,111 @@ pub struct PrintArgs {
     pub file_path: PathBuf,
 }
 
+/// Required arguments for a capacity command
+#[derive(Args, Debug, Eq, PartialEq)]
+pub struct CapacityArgs {
+    /// Path to the PNG file
+    pub file_path: PathBuf,
+
+    /// Channels used by the lsb method, as a combination of R, G, B and A
+    #[arg(long, default_value = "rgb", value_parser = Channels::from_str)]
+    pub channels: Channels,
+}
+
+/// Required arguments for an analyze command
+#[derive(Args, Debug, Eq, PartialEq)]
+pub struct AnalyzeArgs {
+    /// Path to the PNG file
+    pub file_path: PathBuf,
+
+    /// Print the report as JSON
+    #[arg(long)]
+    pub json: bool,
+}
+
+/// Required arguments for a validate command
+#[derive(Args, Debug, Eq, PartialEq)]
+pub struct ValidateArgs {
+    /// Path to the PNG file
+    pub file_path: PathBuf,
+}
+
+/// Required arguments for a repair command
+#[derive(Args, Debug, Eq, PartialEq)]
+pub struct RepairArgs {
+    /// Path to the damaged PNG file
+    pub infile_path: PathBuf,
+
+    /// Path to the repaired PNG file (optional, defaults to overwriting the input)
+    pub outfile_path: Option<PathBuf>,
+}
+
+/// Required arguments for a text command
+#[derive(Args, Debug, Eq, PartialEq)]
+pub struct TextArgs {
+    /// What to do with the textual metadata
+    #[command(subcommand)]
+    pub action: TextAction,
+}
+
+/// Operations on the textual metadata of a PNG file
+#[derive(Subcommand, Debug, Eq, PartialEq)]
+pub enum TextAction {
+    /// Print the text stored under a keyword
+    Get(TextKeyArgs),
+
+    /// Store a text under a keyword, replacing any text already stored under it
+    Set(TextSetArgs),
+
+    /// Print every keyword and its text
+    List(TextListArgs),
+
+    /// Remove every text stored under a keyword
+    Delete(TextKeyArgs),
+}
+
+/// Required arguments for the text get and delete commands
+#[derive(Args, Debug, Eq, PartialEq)]
+pub struct TextKeyArgs {
+    /// Path to the PNG file
+    pub file_path: PathBuf,
+
+    /// Keyword naming the text (like "Title")
+    pub keyword: String,
+}
+
+/// Required arguments for a text set command
+#[derive(Args, Debug, Eq, PartialEq)]
+pub struct TextSetArgs {
+    /// Path to the PNG file
+    pub file_path: PathBuf,
+
+    /// Keyword naming the text (like "Title"): 1-79 Latin-1 characters
+    pub keyword: String,
+
+    /// The text to store
+    pub text: String,
+
+    /// Store the text deflated, in a zTXt chunk or a compressed iTXt chunk
+    #[arg(long)]
+    pub compress: bool,
+
+    /// Language of the text (like "ja" or "en-GB"); stores the text in an iTXt chunk
+    #[arg(long)]
+    pub language: Option<String>,
+
+    /// The keyword translated into the language of the text; stores the text in an iTXt chunk
+    #[arg(long)]
+    pub translated_keyword: Option<String>,
+}
+
+/// Required arguments for a text list command
+#[derive(Args, Debug, Eq, PartialEq)]
+pub struct TextListArgs {
+    /// Path to the PNG file
+    pub file_path: PathBuf,
+}
+
 // #[cfg(test)]
 // mod test {
 //     #[allow(unused_imports)]
@@ -173,3 +454,16 @@ pub struct PrintArgs {
 //         todo!()
 //     }
 // }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_binary_encoding() {
+        let bytes = [0x00, 0xff, 0x10, b'a'];
+        assert_eq!(BinaryEncoding::Hex.encode(&bytes), "00ff1061");
+        assert_eq!(BinaryEncoding::Base64.encode(&bytes), "AP8QYQ==");
+        assert_eq!(BinaryEncoding::Base64.encode(&[]), "");
+    }
+}
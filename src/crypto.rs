@@ -0,0 +1,344 @@
+//! Implements passphrase-based authenticated encryption of hidden messages.
+
+use crate::{
+    error::{ArgumentError, EnvelopeError, KdfError, ProgramError},
+    ProgramResult,
+};
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::{
+    aead::{rand_core::RngCore, Aead, KeyInit, OsRng, Payload},
+    XChaCha20Poly1305, XNonce,
+};
+
+/// Marks the start of an encrypted envelope
+pub const MAGIC: [u8; 4] = *b"imcE";
+
+/// Current version of the envelope layout
+pub const VERSION: u8 = 1;
+
+/// Largest Argon2 memory size accepted from an envelope, in KiB (1 GiB)
+pub const MAX_M_COST: u32 = 1024 * 1024;
+
+/// Largest number of Argon2 iterations accepted from an envelope
+pub const MAX_T_COST: u32 = 16;
+
+/// Largest Argon2 degree of parallelism accepted from an envelope
+pub const MAX_P_COST: u32 = 16;
+
+/// Algorithm id for Argon2id key derivation with XChaCha20-Poly1305 encryption
+pub const ARGON2ID_XCHACHA20POLY1305: u8 = 1;
+
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 24;
+const KEY_LEN: usize = 32;
+const TAG_LEN: usize = 16;
+
+/// Length of the header fields authenticated as associated data: magic bytes,
+/// version, algorithm id and KDF parameters
+const AAD_LEN: usize = 4 + 1 + 1 + 12;
+
+/// Length of an envelope header in bytes
+pub const HEADER_LEN: usize = 4 + 1 + 1 + 12 + SALT_LEN + NONCE_LEN;
+
+/// Number of bytes an envelope adds to the plaintext: the header and the authentication tag
+pub const OVERHEAD: usize = HEADER_LEN + TAG_LEN;
+
+/// Cost parameters of the Argon2id key derivation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KdfParams {
+    /// Memory size in KiB
+    pub m_cost: u32,
+    /// Number of iterations
+    pub t_cost: u32,
+    /// Degree of parallelism
+    pub p_cost: u32,
+}
+
+impl Default for KdfParams {
+    /// The Argon2id parameters recommended by OWASP (19 MiB, 2 iterations, 1 lane)
+    fn default() -> Self {
+        Self {
+            m_cost: 19 * 1024,
+            t_cost: 2,
+            p_cost: 1,
+        }
+    }
+}
+
+/// Returns true if `data` is long enough to hold an encrypted envelope and starts with
+/// a header of the current version and algorithm
+pub fn is_envelope(data: &[u8]) -> bool {
+    data.len() >= OVERHEAD
+        && data[..4] == MAGIC
+        && data[4] == VERSION
+        && data[5] == ARGON2ID_XCHACHA20POLY1305
+}
+
+/// Encrypts `plaintext` with a key derived from `passphrase` using the default `KdfParams`.
+pub fn encrypt(plaintext: &[u8], passphrase: &[u8]) -> ProgramResult<Vec<u8>> {
+    encrypt_with_params(plaintext, passphrase, KdfParams::default())
+}
+
+/// Encrypts `plaintext` with a key derived from `passphrase` and wraps it in a versioned envelope.
+///
+/// The envelope is laid out as follows:
+/// 1. Magic bytes `imcE` *(4 bytes)*
+/// 2. Envelope version *(1 byte)*
+/// 3. Algorithm id *(1 byte)*
+/// 4. Argon2 memory, iterations and parallelism *(4 bytes each)*
+/// 5. KDF salt *(16 bytes)*
+/// 6. Nonce *(24 bytes)*
+/// 7. The ciphertext followed by its authentication tag
+///
+/// Fields 1 to 4 are authenticated as associated data. This function returns
+/// `ProgramError::InvalidArgument` if `params` exceed the limits `decrypt` accepts.
+pub fn encrypt_with_params(
+    plaintext: &[u8],
+    passphrase: &[u8],
+    params: KdfParams,
+) -> ProgramResult<Vec<u8>> {
+    check_limits(params).map_err(|e| ProgramError::InvalidArgument(ArgumentError::Kdf(e)))?;
+    let mut salt = [0u8; SALT_LEN];
+    let mut nonce = [0u8; NONCE_LEN];
+    OsRng.fill_bytes(&mut salt);
+    OsRng.fill_bytes(&mut nonce);
+
+    let mut envelope: Vec<u8> = MAGIC
+        .iter()
+        .chain([VERSION, ARGON2ID_XCHACHA20POLY1305].iter())
+        .chain(params.m_cost.to_be_bytes().iter())
+        .chain(params.t_cost.to_be_bytes().iter())
+        .chain(params.p_cost.to_be_bytes().iter())
+        .chain(salt.iter())
+        .chain(nonce.iter())
+        .copied()
+        .collect();
+    let payload = Payload {
+        msg: plaintext,
+        aad: &envelope[..AAD_LEN],
+    };
+    let ciphertext = cipher(passphrase, &salt, params)?
+        .encrypt(XNonce::from_slice(&nonce), payload)
+        .map_err(|_| ProgramError::InvalidEnvelope(EnvelopeError::EncryptionFailed))?;
+    envelope.extend(ciphertext);
+    Ok(envelope)
+}
+
+/// Opens an envelope created by `encrypt` and returns the plaintext.
+///
+/// This function returns `ProgramError::AuthenticationFailed` if the passphrase is wrong
+/// or the envelope has been tampered with, and `ProgramError::InvalidEnvelope` if its
+/// KDF parameters exceed `MAX_M_COST`, `MAX_T_COST` or `MAX_P_COST`.
+pub fn decrypt(envelope: &[u8], passphrase: &[u8]) -> ProgramResult<Vec<u8>> {
+    if envelope.len() < OVERHEAD || envelope[..4] != MAGIC {
+        return Err(ProgramError::InvalidEnvelope(EnvelopeError::NotAnEnvelope));
+    }
+    if envelope[4] != VERSION {
+        return Err(ProgramError::InvalidEnvelope(EnvelopeError::Version(
+            envelope[4],
+        )));
+    }
+    if envelope[5] != ARGON2ID_XCHACHA20POLY1305 {
+        return Err(ProgramError::InvalidEnvelope(EnvelopeError::Algorithm(
+            envelope[5],
+        )));
+    }
+
+    let field = |at: usize| {
+        u32::from_be_bytes([
+            envelope[at],
+            envelope[at + 1],
+            envelope[at + 2],
+            envelope[at + 3],
+        ])
+    };
+    let params = KdfParams {
+        m_cost: field(6),
+        t_cost: field(10),
+        p_cost: field(14),
+    };
+    // The envelope is untrusted: unchecked costs could demand terabytes of memory or
+    // run the key derivation for days
+    check_limits(params).map_err(|e| ProgramError::InvalidEnvelope(EnvelopeError::Kdf(e)))?;
+    let salt = &envelope[AAD_LEN..AAD_LEN + SALT_LEN];
+    let nonce = &envelope[AAD_LEN + SALT_LEN..HEADER_LEN];
+
+    let payload = Payload {
+        msg: &envelope[HEADER_LEN..],
+        aad: &envelope[..AAD_LEN],
+    };
+    // The parameters came from the envelope, so a failed derivation means a bad envelope
+    let cipher = cipher(passphrase, salt, params).map_err(|e| match e {
+        ProgramError::InvalidArgument(ArgumentError::Kdf(e)) => {
+            ProgramError::InvalidEnvelope(EnvelopeError::Kdf(e))
+        }
+        e => e,
+    })?;
+    cipher
+        .decrypt(XNonce::from_slice(nonce), payload)
+        .map_err(|_| ProgramError::AuthenticationFailed)
+}
+
+/// Checks `params` against `MAX_M_COST`, `MAX_T_COST` and `MAX_P_COST`, reporting the
+/// first one exceeded.
+fn check_limits(params: KdfParams) -> Result<(), KdfError> {
+    for (param, value, max) in [
+        ("memory size", params.m_cost, MAX_M_COST),
+        ("iteration count", params.t_cost, MAX_T_COST),
+        ("parallelism", params.p_cost, MAX_P_COST),
+    ] {
+        if value > max {
+            return Err(KdfError::Limit { param, value, max });
+        }
+    }
+    Ok(())
+}
+
+/// Derives a 32-byte key from `passphrase` and `salt` with Argon2id.
+///
+/// The salt must be at least 8 bytes long.
+pub fn derive_key(passphrase: &[u8], salt: &[u8], params: KdfParams) -> ProgramResult<[u8; 32]> {
+    let params = Params::new(params.m_cost, params.t_cost, params.p_cost, Some(KEY_LEN))
+        .map_err(|e| ProgramError::InvalidArgument(ArgumentError::Kdf(KdfError::Params(e))))?;
+    let mut key = [0u8; KEY_LEN];
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase, salt, &mut key)
+        .map_err(|e| ProgramError::InvalidArgument(ArgumentError::Kdf(KdfError::Derivation(e))))?;
+    Ok(key)
+}
+
+/// Derives the cipher key from `passphrase` and `salt`.
+fn cipher(passphrase: &[u8], salt: &[u8], params: KdfParams) -> ProgramResult<XChaCha20Poly1305> {
+    let key = derive_key(passphrase, salt, params)?;
+    Ok(XChaCha20Poly1305::new(&key.into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MESSAGE: &[u8] = b"This is where your secret message will be!";
+
+    // Cheap parameters so the tests stay fast in debug builds
+    const TEST_PARAMS: KdfParams = KdfParams {
+        m_cost: 64,
+        t_cost: 1,
+        p_cost: 1,
+    };
+
+    #[test]
+    fn test_encrypt_decrypt() {
+        let envelope = encrypt_with_params(MESSAGE, b"hunter2", TEST_PARAMS).unwrap();
+        assert!(is_envelope(&envelope));
+        assert_eq!(envelope.len(), MESSAGE.len() + OVERHEAD);
+        assert_eq!(decrypt(&envelope, b"hunter2").unwrap(), MESSAGE);
+    }
+
+    #[test]
+    fn test_wrong_passphrase() {
+        let envelope = encrypt_with_params(MESSAGE, b"hunter2", TEST_PARAMS).unwrap();
+        assert!(matches!(
+            decrypt(&envelope, b"hunter3"),
+            Err(ProgramError::AuthenticationFailed)
+        ));
+    }
+
+    #[test]
+    fn test_tampered_envelope() {
+        let mut envelope = encrypt_with_params(MESSAGE, b"hunter2", TEST_PARAMS).unwrap();
+        let last = envelope.len() - 1;
+        envelope[last] ^= 1;
+        assert!(matches!(
+            decrypt(&envelope, b"hunter2"),
+            Err(ProgramError::AuthenticationFailed)
+        ));
+    }
+
+    #[test]
+    fn test_unsupported_version() {
+        let mut envelope = encrypt_with_params(MESSAGE, b"hunter2", TEST_PARAMS).unwrap();
+        envelope[4] = 9;
+        assert!(matches!(
+            decrypt(&envelope, b"hunter2"),
+            Err(ProgramError::InvalidEnvelope(EnvelopeError::Version(9)))
+        ));
+    }
+
+    #[test]
+    fn test_excessive_kdf_params() {
+        let envelope = encrypt_with_params(MESSAGE, b"hunter2", TEST_PARAMS).unwrap();
+        for (at, value) in [(6, MAX_M_COST + 1), (10, MAX_T_COST + 1), (14, u32::MAX)] {
+            let mut envelope = envelope.clone();
+            envelope[at..at + 4].copy_from_slice(&value.to_be_bytes());
+            assert!(matches!(
+                decrypt(&envelope, b"hunter2"),
+                Err(ProgramError::InvalidEnvelope(EnvelopeError::Kdf(
+                    KdfError::Limit { .. }
+                )))
+            ));
+        }
+
+        let params = KdfParams {
+            m_cost: u32::MAX,
+            ..TEST_PARAMS
+        };
+        assert!(matches!(
+            encrypt_with_params(MESSAGE, b"hunter2", params),
+            Err(ProgramError::InvalidArgument(ArgumentError::Kdf(
+                KdfError::Limit {
+                    param: "memory size",
+                    ..
+                }
+            )))
+        ));
+    }
+
+    #[test]
+    fn test_header_is_authenticated() {
+        let envelope = encrypt_with_params(MESSAGE, b"hunter2", TEST_PARAMS).unwrap();
+        // The same ciphertext sealed without the header as associated data does not open
+        let cipher = cipher(
+            b"hunter2",
+            &envelope[AAD_LEN..AAD_LEN + SALT_LEN],
+            TEST_PARAMS,
+        );
+        let nonce = XNonce::from_slice(&envelope[AAD_LEN + SALT_LEN..HEADER_LEN]);
+        let mut unbound = envelope[..HEADER_LEN].to_vec();
+        unbound.extend(cipher.unwrap().encrypt(nonce, MESSAGE).unwrap());
+        assert!(matches!(
+            decrypt(&unbound, b"hunter2"),
+            Err(ProgramError::AuthenticationFailed)
+        ));
+    }
+
+    #[test]
+    fn test_invalid_kdf_params() {
+        let mut envelope = encrypt_with_params(MESSAGE, b"hunter2", TEST_PARAMS).unwrap();
+        envelope[14..18].copy_from_slice(&0u32.to_be_bytes());
+        assert!(matches!(
+            decrypt(&envelope, b"hunter2"),
+            Err(ProgramError::InvalidEnvelope(EnvelopeError::Kdf(
+                KdfError::Params(_)
+            )))
+        ));
+    }
+
+    #[test]
+    fn test_plaintext_is_not_envelope() {
+        assert!(!is_envelope(MESSAGE));
+    }
+
+    #[test]
+    fn test_magic_alone_is_not_envelope() {
+        let envelope = encrypt_with_params(MESSAGE, b"hunter2", TEST_PARAMS).unwrap();
+        assert!(!is_envelope(&envelope[..OVERHEAD - 1]));
+
+        let mut padded = MAGIC.to_vec();
+        padded.resize(OVERHEAD, 0);
+        assert!(!is_envelope(&padded));
+
+        let mut tampered = envelope.clone();
+        tampered[5] = 9;
+        assert!(!is_envelope(&tampered));
+    }
+}
@@ -1,30 +1,583 @@
 //! Error types
 
+use crate::png::header::ColorType;
+
 /// Representing the various errors associated with this application.
 #[derive(Debug, thiserror::Error)]
 pub enum ProgramError {
-    /// Error originating from string conversion from
-    /// UTF-8 byte vector
-    #[error("{0}")]
-    StrFromUtf8Error(String),
+    /// Error originating from the underlying I/O
+    #[error("I/O error{}: {source}", at_offset(.offset))]
+    Io {
+        /// The underlying error
+        source: std::io::Error,
+        /// Byte offset of the field being read, when parsing a file
+        offset: Option<usize>,
+    },
+
+    /// The first eight bytes of the input are not the PNG signature
+    #[error("Bad PNG signature at offset 0: found {found:?}")]
+    BadSignature {
+        /// The bytes found in place of the signature
+        found: [u8; 8],
+    },
+
+    /// The stored CRC of a chunk does not match the computed one
+    #[error(
+        "CRC mismatch in {chunk_type} chunk at offset {offset}: expected {expected}, found {actual}"
+    )]
+    CrcMismatch {
+        /// Type of the chunk whose CRC is wrong
+        chunk_type: String,
+        /// The CRC computed over the chunk type and data
+        expected: u32,
+        /// The CRC stored in the chunk
+        actual: u32,
+        /// Byte offset of the CRC field
+        offset: usize,
+    },
+
+    /// The input ended before a complete field could be read
+    #[error("Unexpected end of data at offset {offset}")]
+    Truncated {
+        /// Byte offset of the incomplete field
+        offset: usize,
+    },
+
+    /// A chunk type code that violates the PNG naming rules
+    #[error("Invalid chunk type {code:?}{}: {reason}", at_offset(.offset))]
+    InvalidChunkType {
+        /// The rejected chunk type code, lossily decoded
+        code: String,
+        /// Why the chunk type code was rejected
+        reason: ChunkTypeError,
+        /// Byte offset of the chunk type field, when parsed from a file
+        offset: Option<usize>,
+    },
+
+    /// No chunk of the requested type exists
+    #[error("No {chunk_type} chunk found{}", before_offset(.offset))]
+    ChunkNotFound {
+        /// The requested chunk type
+        chunk_type: String,
+        /// Byte offset at which the search ended, when reading a file
+        offset: Option<usize>,
+    },
+
+    /// A chunk length that exceeds the maximum of 2^31-1 bytes
+    #[error("Chunk length {length} at offset {offset} exceeds the maximum of 2^31-1 bytes")]
+    LengthOverflow {
+        /// The declared chunk length
+        length: u32,
+        /// Byte offset of the length field
+        offset: usize,
+    },
+
+    /// A malformed or inconsistent payload segment
+    #[error("Invalid segment: {0}")]
+    InvalidSegment(SegmentError),
+
+    /// A payload is missing one of its segments
+    #[error("Payload {payload_id} is missing segment {index}")]
+    MissingSegment {
+        /// Identifies the incomplete payload
+        payload_id: u32,
+        /// Sequence index of the first missing segment
+        index: u32,
+    },
+
+    /// A payload holds more than one segment with the same sequence index
+    #[error("Payload {payload_id} holds segment {index} more than once")]
+    DuplicateSegment {
+        /// Identifies the affected payload
+        payload_id: u32,
+        /// Sequence index of the duplicated segment
+        index: u32,
+    },
+
+    /// An `IHDR` chunk that violates the PNG spec
+    #[error("Invalid image header: {0}")]
+    InvalidImageHeader(ImageHeaderError),
+
+    /// Image data that cannot be decoded
+    #[error("Invalid image data: {0}")]
+    InvalidImageData(ImageDataError),
+
+    /// A field of a textual chunk (`tEXt`, `zTXt` or `iTXt`) breaks the PNG spec
+    #[error("Invalid text chunk {field}: {reason}")]
+    InvalidText {
+        /// The offending field, such as "keyword"
+        field: &'static str,
+        /// Why the field is invalid
+        reason: String,
+    },
+
+    /// Bytes read as text that are not valid UTF-8
+    #[error("Invalid UTF-8 at byte {offset}")]
+    InvalidUtf8 {
+        /// Position of the first invalid byte within the decoded bytes
+        offset: usize,
+    },
+
+    /// A valid PNG feature that this crate does not handle
+    #[error("Unsupported: {0}")]
+    Unsupported(UnsupportedFeature),
+
+    /// A command-line or API argument outside of its allowed values
+    #[error("Invalid argument: {0}")]
+    InvalidArgument(ArgumentError),
+
+    /// A payload that does not fit into the space available for it
+    #[error("Payload of {needed} bytes exceeds the available capacity of {available} bytes")]
+    CapacityExceeded {
+        /// Size of the payload in bytes
+        needed: usize,
+        /// Largest payload that fits, in bytes
+        available: usize,
+    },
+
+    /// No embedded payload was found
+    #[error("No payload found {0}")]
+    PayloadNotFound(PayloadLocation),
+
+    /// A malformed or unsupported encrypted envelope
+    #[error("Invalid envelope: {0}")]
+    InvalidEnvelope(EnvelopeError),
+
+    /// The passphrase is wrong or the encrypted message has been tampered with
+    #[error("Authentication failed: wrong passphrase or corrupted message")]
+    AuthenticationFailed,
+
+    /// The passphrase and its confirmation differ
+    #[error("Passphrases do not match")]
+    PassphraseMismatch,
+
+    /// The file breaks structural rules of the PNG spec
+    #[error("File breaks {errors} structural rules of the PNG spec")]
+    InvalidStructure {
+        /// Number of rules broken
+        errors: usize,
+    },
+}
+
+/// Representing the reasons a chunk type code can be rejected.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum ChunkTypeError {
+    /// The code is not exactly 4 bytes long
+    #[error("must be exactly 4 bytes long, received {0} bytes")]
+    Length(usize),
+
+    /// The code contains a byte outside of `A-Z` and `a-z`
+    #[error("byte {byte:#04x} at index {index} is not an ASCII letter")]
+    InvalidByte {
+        /// The offending byte
+        byte: u8,
+        /// Position of the offending byte within the code
+        index: usize,
+    },
+
+    /// The reserved bit (third byte) is set
+    #[error("reserved bit is set; the third letter must be uppercase")]
+    ReservedBit,
+}
+
+/// Representing the reasons a payload segment can be rejected.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum SegmentError {
+    /// The requested segment size is zero or too large for a single chunk
+    #[error("segment size must be between 1 and {max} bytes, received {size}")]
+    Size {
+        /// The requested segment size
+        size: usize,
+        /// The largest segment size allowed
+        max: usize,
+    },
+
+    /// The payload needs more segments than can be numbered
+    #[error("payload needs more than 2^32-1 segments")]
+    TooMany,
+
+    /// There are no segments to put back together
+    #[error("no segments to reassemble")]
+    Empty,
+
+    /// A segment disagrees with the first one about the total count, or lies beyond it
+    #[error(
+        "segment {index} of payload {payload_id} claims a total of {total}, expected {expected}"
+    )]
+    Total {
+        /// Identifies the affected payload
+        payload_id: u32,
+        /// Sequence index of the offending segment
+        index: u32,
+        /// The total count the segment claims
+        total: u32,
+        /// The total count claimed by the first segment
+        expected: u32,
+    },
+
+    /// Chunk data that does not start with a well-formed segment header
+    #[error("{chunk_type} chunk does not hold a segment header")]
+    NoHeader {
+        /// Type of the chunk holding the data
+        chunk_type: String,
+    },
+}
+
+/// Representing the reasons an `IHDR` chunk can be rejected.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum ImageHeaderError {
+    /// The chunk data is not 13 bytes long
+    #[error("IHDR data must be 13 bytes long, received {0}")]
+    Length(usize),
+
+    /// A zero width or height, or one above 2^31-1
+    #[error("image dimensions must be between 1 and 2^31-1, received {width}x{height}")]
+    Dimensions {
+        /// The declared width in pixels
+        width: u32,
+        /// The declared height in pixels
+        height: u32,
+    },
+
+    /// A bit depth the PNG spec does not allow for the color type
+    #[error("bit depth {bit_depth} is not allowed for color type {} ({})", .color_type, .color_type.code())]
+    BitDepth {
+        /// The declared bit depth
+        bit_depth: u8,
+        /// The declared color type
+        color_type: ColorType,
+    },
+
+    /// A color type code outside of the PNG spec
+    #[error("unknown color type {0}")]
+    ColorType(u8),
+
+    /// A compression method other than 0
+    #[error("unknown compression method {0}")]
+    CompressionMethod(u8),
+
+    /// A filter method other than 0
+    #[error("unknown filter method {0}")]
+    FilterMethod(u8),
+
+    /// An interlace method other than 0 or 1
+    #[error("unknown interlace method {0}")]
+    InterlaceMethod(u8),
+
+    /// The chunk being read as the image header is not an `IHDR` chunk
+    #[error("expected an IHDR chunk, received {chunk_type}")]
+    NotIhdr {
+        /// Type of the chunk received instead
+        chunk_type: String,
+    },
+}
+
+/// Representing the reasons image data cannot be decoded or encoded.
+#[derive(Debug, thiserror::Error)]
+pub enum ImageDataError {
+    /// Unfiltered pixel data of the wrong length for the image header
+    #[error("expected {expected} bytes of pixel data, received {actual}")]
+    Length {
+        /// Length the image header calls for
+        expected: usize,
+        /// Length received
+        actual: usize,
+    },
+
+    /// The image data of all the passes together overflows `usize`
+    #[error("image data is too large to address")]
+    TooLarge,
+
+    /// The scanlines of an image overflow `usize`
+    #[error("{height} scanlines of {row_bytes} bytes are too large to address")]
+    Scanlines {
+        /// Number of scanlines
+        height: u32,
+        /// Length of each scanline in bytes
+        row_bytes: usize,
+    },
+
+    /// The samples of an image overflow `usize`
+    #[error("{width}x{height} images hold too many samples to address")]
+    Samples {
+        /// Width of the image in pixels
+        width: u32,
+        /// Height of the image in pixels
+        height: u32,
+    },
+
+    /// A `PLTE` chunk that does not hold 1 to 256 RGB entries
+    #[error("PLTE data must hold 1 to 256 RGB entries, received {0} bytes")]
+    Palette(usize),
+
+    /// The concatenated `IDAT` data is not a valid zlib stream
+    #[error("corrupt zlib stream: {0}")]
+    Zlib(std::io::Error),
 
-    /// Error from `TryFrom` trait implementations
+    /// The zlib stream inflates to the wrong length for the image header
+    #[error("expected {expected} bytes of filtered image data, inflated {actual}")]
+    Inflated {
+        /// Length the image header calls for
+        expected: usize,
+        /// Length inflated, or one byte more than `expected` if the stream is longer
+        actual: usize,
+    },
+
+    /// A scanline filter type outside of the PNG spec
+    #[error("unknown filter type {0}")]
+    FilterType(u8),
+
+    /// Pixels whose header does not match the `IHDR` chunk they are stored under
+    #[error("pixels do not match the IHDR chunk")]
+    HeaderMismatch,
+
+    /// An `IDAT` chunk size of zero or above 2^31-1
+    #[error("IDAT size must be between 1 and 2^31-1 bytes, received {0}")]
+    IdatSize(usize),
+}
+
+/// Representing the valid PNG features this crate does not handle.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum UnsupportedFeature {
+    /// LSB embedding in an indexed image
+    #[error("LSB embedding in indexed images; use palette embedding instead")]
+    LsbIndexed,
+
+    /// Palette embedding in an image that is not indexed
+    #[error("palette embedding in images that are not indexed")]
+    PaletteNotIndexed,
+
+    /// Palette embedding in a palette holding the same entry more than once
+    #[error("palette embedding in palettes with duplicate entries")]
+    PaletteDuplicates,
+}
+
+/// Representing the reasons a command-line or API argument can be rejected.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum ArgumentError {
+    /// An unknown chunk position name
+    #[error(
+        "unknown chunk position {0:?}; expected one of: before-iend, after-ihdr, before-idat, end"
+    )]
+    ChunkPosition(String),
+
+    /// A channel letter other than R, G, B or A
+    #[error("unknown channel {0:?}; expected a combination of R, G, B and A")]
+    Channel(char),
+
+    /// An empty set of channels
+    #[error("at least one channel must be selected")]
+    NoChannels,
+
+    /// None of the selected channels exist in the image
+    #[error("none of the selected channels exist in {0} images")]
+    MissingChannels(ColorType),
+
+    /// A number of bits per channel the image cannot hold
+    #[error("bits per channel must be between 1 and {max}, received {bits}")]
+    Bits {
+        /// The requested number of bits
+        bits: u8,
+        /// The largest number of bits allowed for the image
+        max: u8,
+    },
+
+    /// The chunk method was selected without a chunk type
+    #[error("the chunk method needs a chunk type")]
+    MissingChunkType,
+
+    /// Key derivation parameters that cannot be used
     #[error("{0}")]
-    TryFromError(String),
+    Kdf(KdfError),
+}
+
+/// Representing the places an embedded payload is looked for.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum PayloadLocation {
+    /// Data appended after the `IEND` chunk
+    #[error("after the IEND chunk")]
+    Trailing,
+
+    /// The least significant bits of the pixel samples
+    #[error("in the low bits of the pixels")]
+    Lsb,
+
+    /// The order of the palette entries
+    #[error("in the palette order")]
+    Palette,
 
-    /// Error originating from manipulating PNG chunks
+    /// The textual chunks stored under a keyword
+    #[error("under text keyword {keyword:?}")]
+    Text {
+        /// The keyword looked up
+        keyword: String,
+    },
+}
+
+/// Representing the reasons an encrypted envelope can be rejected.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum EnvelopeError {
+    /// The data is too short or does not start with the envelope magic bytes
+    #[error("data does not hold an encrypted envelope")]
+    NotAnEnvelope,
+
+    /// An envelope version other than the current one
+    #[error("unsupported envelope version {0}")]
+    Version(u8),
+
+    /// An unknown algorithm id
+    #[error("unsupported algorithm id {0}")]
+    Algorithm(u8),
+
+    /// Key derivation parameters that cannot be used
     #[error("{0}")]
-    ChunkOperationError(String),
+    Kdf(KdfError),
+
+    /// The cipher failed to seal the plaintext
+    #[error("encryption failed")]
+    EncryptionFailed,
+}
+
+/// Representing the reasons the Argon2 key derivation can be rejected.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum KdfError {
+    /// A cost parameter above the limit `crypto::decrypt` accepts
+    #[error("Argon2 {param} {value} exceeds the limit of {max}")]
+    Limit {
+        /// Name of the parameter, such as "memory size"
+        param: &'static str,
+        /// The requested value
+        value: u32,
+        /// The largest value accepted
+        max: u32,
+    },
+
+    /// Parameters that Argon2 rejects
+    #[error("invalid KDF parameters: {0}")]
+    Params(argon2::Error),
+
+    /// The key derivation itself failed
+    #[error("key derivation failed: {0}")]
+    Derivation(argon2::Error),
+}
+
+impl ProgramError {
+    /// Exit code reported by the command-line app when a requested chunk is missing
+    pub const EXIT_NOT_FOUND: i32 = 3;
+
+    /// Exit code reported by the command-line app when an encrypted message cannot be opened
+    pub const EXIT_AUTH_FAILED: i32 = 4;
+
+    /// Exit code reported by the command-line app when a file fails validation
+    pub const EXIT_INVALID: i32 = 5;
+
+    /// Returns the process exit code the command-line app reports for this error.
+    ///
+    /// `EXIT_NOT_FOUND` signals that no matching chunk or payload (and so no message) was found and
+    /// `EXIT_AUTH_FAILED` that decryption failed, and `EXIT_INVALID` that a file broke the rules
+    /// checked by `Png::validate`; every other error exits with `1`.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Self::ChunkNotFound { .. } | Self::PayloadNotFound(_) => Self::EXIT_NOT_FOUND,
+            Self::AuthenticationFailed => Self::EXIT_AUTH_FAILED,
+            Self::InvalidStructure { .. } => Self::EXIT_INVALID,
+            _ => 1,
+        }
+    }
+
+    /// Returns the byte offset at which this error was detected, if known.
+    pub fn offset(&self) -> Option<usize> {
+        match self {
+            Self::BadSignature { .. } => Some(0),
+            Self::CrcMismatch { offset, .. }
+            | Self::Truncated { offset }
+            | Self::LengthOverflow { offset, .. } => Some(*offset),
+            Self::InvalidChunkType { offset, .. }
+            | Self::Io { offset, .. }
+            | Self::ChunkNotFound { offset, .. } => *offset,
+            _ => None,
+        }
+    }
+
+    /// Shifts the offset carried by this error by `base` bytes.
+    ///
+    /// Used to turn offsets relative to a single chunk into offsets relative to the whole file.
+    pub(crate) fn offset_by(mut self, base: usize) -> Self {
+        match &mut self {
+            Self::CrcMismatch { offset, .. }
+            | Self::Truncated { offset }
+            | Self::LengthOverflow { offset, .. } => *offset += base,
+            Self::InvalidChunkType { offset, .. } => {
+                *offset = Some(offset.unwrap_or(0) + base);
+            }
+            Self::Io {
+                offset: Some(offset),
+                ..
+            }
+            | Self::ChunkNotFound {
+                offset: Some(offset),
+                ..
+            } => *offset += base,
+            _ => {}
+        }
+        self
+    }
+}
+
+fn at_offset(offset: &Option<usize>) -> String {
+    match offset {
+        Some(offset) => format!(" at offset {}", offset),
+        None => String::new(),
+    }
+}
+
+fn before_offset(offset: &Option<usize>) -> String {
+    match offset {
+        Some(offset) => format!(" before offset {}", offset),
+        None => String::new(),
+    }
 }
 
 impl From<std::io::Error> for ProgramError {
-    fn from(e: std::io::Error) -> Self {
-        Self::TryFromError(e.to_string())
+    fn from(source: std::io::Error) -> Self {
+        Self::Io {
+            source,
+            offset: None,
+        }
     }
 }
 
 impl From<std::string::FromUtf8Error> for ProgramError {
     fn from(e: std::string::FromUtf8Error) -> Self {
-        Self::StrFromUtf8Error(e.to_string())
+        Self::InvalidUtf8 {
+            offset: e.utf8_error().valid_up_to(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exit_codes() {
+        let not_found = ProgramError::ChunkNotFound {
+            chunk_type: "ruSt".to_string(),
+            offset: None,
+        };
+        assert_eq!(not_found.exit_code(), ProgramError::EXIT_NOT_FOUND);
+        assert_eq!(
+            ProgramError::PayloadNotFound(PayloadLocation::Trailing).exit_code(),
+            ProgramError::EXIT_NOT_FOUND
+        );
+        assert_eq!(
+            ProgramError::AuthenticationFailed.exit_code(),
+            ProgramError::EXIT_AUTH_FAILED
+        );
+        assert_eq!(
+            ProgramError::InvalidStructure { errors: 1 }.exit_code(),
+            ProgramError::EXIT_INVALID
+        );
+        assert_eq!(ProgramError::PassphraseMismatch.exit_code(), 1);
     }
 }
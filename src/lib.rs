@@ -10,8 +10,12 @@
 #![warn(unused_qualifications)]
 #![warn(unused_results)]
 
+/// Detection of hidden messages in PNG files
+pub mod analysis;
 /// Command-line arguments parsing
 pub mod args;
+/// Passphrase-based encryption of hidden messages
+pub mod crypto;
 /// Program-related errors
 pub mod error;
 /// Implementation of `Chunk`, `ChunkType` and `Png`
@@ -10,16 +10,23 @@ fn run(cmd: Command) -> ProgramResult<()> {
         Command::Decode(args) => command::decode(args),
         Command::Remove(args) => command::remove(args),
         Command::Print(args) => command::print(args),
+        Command::Capacity(args) => command::capacity(args),
+        Command::Analyze(args) => command::analyze(args),
+        Command::Validate(args) => command::validate(args),
+        Command::Repair(args) => command::repair(args),
+        Command::Text(args) => command::text(args),
     }
 }
 
-fn main() -> ProgramResult<()> {
+fn main() {
     let app = App::parse();
     if let Some(name) = app.name.as_deref() {
         println!("{}", name);
     }
     if let Some(cmd) = app.command {
-        run(cmd)?;
+        if let Err(e) = run(cmd) {
+            eprintln!("Error: {}", e);
+            std::process::exit(e.exit_code());
+        }
     }
-    Ok(())
 }
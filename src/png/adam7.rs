@@ -0,0 +1,87 @@
+//! Implements the Adam7 interlacing scheme described by the PNG specification.
+//! http://www.libpng.org/pub/png/spec/1.2/PNG-DataRep.html#DR.Interlaced-data-order
+
+/// A single Adam7 pass: the pixels at (`x0 + i * dx`, `y0 + j * dy`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pass {
+    /// Column of the first pixel in the pass
+    pub x0: u32,
+    /// Row of the first pixel in the pass
+    pub y0: u32,
+    /// Distance between columns of the pass
+    pub dx: u32,
+    /// Distance between rows of the pass
+    pub dy: u32,
+}
+
+/// The seven Adam7 passes, in the order they are stored
+#[rustfmt::skip]
+pub const PASSES: [Pass; 7] = [
+    Pass { x0: 0, y0: 0, dx: 8, dy: 8 },
+    Pass { x0: 4, y0: 0, dx: 8, dy: 8 },
+    Pass { x0: 0, y0: 4, dx: 4, dy: 8 },
+    Pass { x0: 2, y0: 0, dx: 4, dy: 4 },
+    Pass { x0: 0, y0: 2, dx: 2, dy: 4 },
+    Pass { x0: 1, y0: 0, dx: 2, dy: 2 },
+    Pass { x0: 0, y0: 1, dx: 1, dy: 2 },
+];
+
+impl Pass {
+    /// The width and height of the reduced image this pass holds for a `width` x `height` image.
+    ///
+    /// Either dimension is zero when the pass is empty.
+    pub fn size(&self, width: u32, height: u32) -> (u32, u32) {
+        let extent = |len: u32, start: u32, step: u32| {
+            if len > start {
+                (len - start).div_ceil(step)
+            } else {
+                0
+            }
+        };
+        (
+            extent(width, self.x0, self.dx),
+            extent(height, self.y0, self.dy),
+        )
+    }
+
+    /// Maps a pixel of the reduced image to its position in the full image
+    pub fn to_full(&self, x: u32, y: u32) -> (u32, u32) {
+        (self.x0 + x * self.dx, self.y0 + y * self.dy)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pass_sizes() {
+        let sizes: Vec<(u32, u32)> = PASSES.iter().map(|p| p.size(5, 5)).collect();
+        assert_eq!(
+            sizes,
+            [(1, 1), (1, 1), (2, 1), (1, 2), (3, 1), (2, 3), (5, 2)]
+        );
+    }
+
+    #[test]
+    fn test_passes_cover_every_pixel_once() {
+        let (width, height) = (13, 11);
+        let mut seen = vec![0; (width * height) as usize];
+        for pass in PASSES {
+            let (w, h) = pass.size(width, height);
+            for y in 0..h {
+                for x in 0..w {
+                    let (fx, fy) = pass.to_full(x, y);
+                    seen[(fy * width + fx) as usize] += 1;
+                }
+            }
+        }
+        assert!(seen.iter().all(|n| *n == 1));
+    }
+
+    #[test]
+    fn test_tiny_image_has_empty_passes() {
+        assert_eq!(PASSES[1].size(1, 1), (0, 1));
+        assert_eq!(PASSES[6].size(1, 1), (1, 0));
+    }
+}
@@ -1,10 +1,10 @@
 //! Implements `Chunk` as described by the PNG specification.
 
 use super::chunk_type::ChunkType;
-use crate::{error::ProgramError, utils::crc_checksum, ProgramResult};
-use std::io::{BufReader, Read};
+use crate::{error::ProgramError, utils::PNG_CRC, ProgramResult};
+use std::io::{ErrorKind, Read};
 
-const MAX_LEN: u32 = 2_u32.pow(31) - 1;
+pub(crate) const MAX_LEN: u32 = 2_u32.pow(31) - 1;
 
 /// A validated PNG chunk. See the PNG Spec for more details
 /// http://www.libpng.org/pub/png/spec/1.2/PNG-Structure.html
@@ -19,14 +19,7 @@ pub struct Chunk {
 impl Chunk {
     /// Creates a new `Chunk` from a `ChunkType` and chunk data
     pub fn new(chunk_type: ChunkType, data: Vec<u8>) -> Self {
-        let chksm_bytes: Vec<u8> = chunk_type
-            .bytes()
-            .iter()
-            .copied()
-            .chain(data.iter().cloned())
-            .collect();
-        let crc = crc_checksum(&chksm_bytes);
-
+        let crc = checksum(&chunk_type, &data);
         Self {
             length: data.len() as u32,
             chunk_type,
@@ -55,6 +48,99 @@ impl Chunk {
         self.crc
     }
 
+    /// Returns true if the stored CRC matches the chunk type and data.
+    ///
+    /// Only chunks parsed with lenient `ParseOptions` can fail this check.
+    pub fn has_valid_crc(&self) -> bool {
+        ChunkRef::from(self).has_valid_crc()
+    }
+
+    /// Recomputes the CRC from the chunk type and data.
+    ///
+    /// Returns true if the stored CRC was wrong and has been replaced.
+    pub fn repair_crc(&mut self) -> bool {
+        let crc = checksum(&self.chunk_type, &self.data);
+        let repaired = self.crc != crc;
+        self.crc = crc;
+        repaired
+    }
+
+    /// Parses a chunk from `bytes` like `Chunk::try_from`, but keeps the stored CRC
+    /// even when it does not match the chunk type and data.
+    pub(crate) fn parse_unchecked(bytes: &[u8]) -> ProgramResult<Self> {
+        ChunkRef::parse_unchecked(bytes).map(|chunk| chunk.to_chunk())
+    }
+
+    /// Reads the next chunk from `reader`, keeping the stored CRC as-is.
+    ///
+    /// Returns `None` if `reader` is at its end before the first byte of the chunk.
+    /// Only the data of this one chunk is held in memory. Offsets carried by any
+    /// returned error are relative to the start of the chunk.
+    pub(crate) fn read_unchecked<R: Read>(reader: &mut R) -> ProgramResult<Option<Self>> {
+        let mut buffer: [u8; 4] = [0; 4];
+
+        // 1. Read the `length` bytes, unless the input is at its end
+        let mut filled = 0;
+        while filled < buffer.len() {
+            match reader.read(&mut buffer[filled..]) {
+                Ok(0) if filled == 0 => return Ok(None),
+                Ok(0) => return Err(ProgramError::Truncated { offset: 0 }),
+                Ok(n) => filled += n,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => {
+                    return Err(ProgramError::Io {
+                        source: e,
+                        offset: Some(0),
+                    })
+                }
+            }
+        }
+        let length = u32::from_be_bytes(buffer);
+        if length > MAX_LEN {
+            return Err(ProgramError::LengthOverflow { length, offset: 0 });
+        }
+
+        // 2. Read the `chunk_type` bytes
+        read_field(reader, &mut buffer, 4)?;
+        let chunk_type = ChunkType::try_from(buffer).map_err(|e| e.offset_by(4))?;
+
+        // 3. Read the `data` bytes, growing the buffer only as bytes arrive
+        let mut data = Vec::new();
+        let _ = reader
+            .by_ref()
+            .take(u64::from(length))
+            .read_to_end(&mut data)
+            .map_err(|e| ProgramError::Io {
+                source: e,
+                offset: Some(8),
+            })?;
+        if data.len() < length as usize {
+            return Err(ProgramError::Truncated { offset: 8 });
+        }
+
+        // 4. Read the `crc` bytes
+        read_field(reader, &mut buffer, 8 + length as usize)?;
+
+        Ok(Some(Self {
+            length,
+            chunk_type,
+            data,
+            crc: u32::from_be_bytes(buffer),
+        }))
+    }
+
+    /// Returns the `ProgramError::CrcMismatch` describing a wrong stored CRC, if any.
+    ///
+    /// The offset of the error is relative to the start of this chunk.
+    pub(crate) fn crc_error(&self) -> Option<ProgramError> {
+        ChunkRef::from(self).crc_error()
+    }
+
+    /// Returns a `ChunkRef` borrowing the data of this chunk.
+    pub fn as_chunk_ref(&self) -> ChunkRef<'_> {
+        ChunkRef::from(self)
+    }
+
     /// Returns the data stored in this chunk as a `String`.
     ///
     /// This function returns an error if the stored data is not valid UTF-8.
@@ -84,62 +170,176 @@ impl Chunk {
 impl TryFrom<&[u8]> for Chunk {
     type Error = ProgramError;
 
+    /// Parses a chunk from `bytes`.
+    ///
+    /// Offsets carried by any returned error are relative to the start of `bytes`.
     fn try_from(bytes: &[u8]) -> ProgramResult<Self> {
-        if bytes.is_empty() {
-            return Err(ProgramError::TryFromError("Chunk is empty".to_string()));
+        let chunk = Self::parse_unchecked(bytes)?;
+        match chunk.crc_error() {
+            Some(e) => Err(e),
+            None => Ok(chunk),
         }
+    }
+}
 
-        let mut reader = BufReader::new(bytes);
-        let mut buffer: [u8; 4] = [0; 4];
+/// A PNG chunk that borrows its data from the bytes it was parsed from.
+///
+/// `ChunkRef` gives the same view of a chunk as `Chunk` without copying the data,
+/// which suits read-only work. `to_chunk` makes an owned `Chunk` when mutation is needed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChunkRef<'a> {
+    length: u32,
+    chunk_type: ChunkType,
+    data: &'a [u8],
+    crc: u32,
+}
 
-        // 1. Read the `length` bytes
-        reader.read_exact(&mut buffer)?;
-        let length = u32::from_be_bytes(buffer);
-        if length > MAX_LEN {
-            return Err(ProgramError::TryFromError(
-                "Length of data received exceeds MAX_LEN(2^31-1)".to_string(),
-            ));
+impl<'a> ChunkRef<'a> {
+    /// The length of the data portion of this chunk.
+    pub fn length(&self) -> u32 {
+        self.length
+    }
+
+    /// The `ChunkType` of this chunk
+    pub fn chunk_type(&self) -> &ChunkType {
+        &self.chunk_type
+    }
+
+    /// The raw data contained in this chunk in bytes, borrowed from the input
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    /// The CRC of this chunk
+    pub fn crc(&self) -> u32 {
+        self.crc
+    }
+
+    /// Returns true if the stored CRC matches the chunk type and data.
+    pub fn has_valid_crc(&self) -> bool {
+        self.crc == checksum(&self.chunk_type, self.data)
+    }
+
+    /// Copies this chunk into an owned `Chunk`.
+    ///
+    /// The stored CRC is kept as-is, so the copy has a valid CRC exactly when this chunk has.
+    pub fn to_chunk(&self) -> Chunk {
+        Chunk {
+            length: self.length,
+            chunk_type: self.chunk_type.clone(),
+            data: self.data.to_vec(),
+            crc: self.crc,
         }
+    }
 
-        // 2. Read the `chunk_type` bytes
-        reader.read_exact(&mut buffer)?;
-        let chunk_type = ChunkType::try_from(buffer)?;
-
-        // 3. Read the `data` bytes
-        let mut data: Vec<u8> = vec![0; length as usize];
-        reader.read_exact(&mut data)?;
-        if data.len() != length as usize {
-            return Err(ProgramError::TryFromError("Length mismatch".to_string()));
+    /// Parses a chunk from the start of `bytes` like `ChunkRef::try_from`, but keeps
+    /// the stored CRC even when it does not match the chunk type and data.
+    pub(crate) fn parse_unchecked(bytes: &'a [u8]) -> ProgramResult<Self> {
+        let field = |start: usize| -> ProgramResult<[u8; 4]> {
+            bytes
+                .get(start..start + 4)
+                .and_then(|field| field.try_into().ok())
+                .ok_or(ProgramError::Truncated { offset: start })
+        };
+
+        // 1. The `length` bytes
+        let length = u32::from_be_bytes(field(0)?);
+        if length > MAX_LEN {
+            return Err(ProgramError::LengthOverflow { length, offset: 0 });
         }
 
-        // 4. Read the `crc` bytes
-        reader.read_exact(&mut buffer)?;
-        let received_crc = u32::from_be_bytes(buffer);
+        // 2. The `chunk_type` bytes
+        let chunk_type = ChunkType::try_from(field(4)?).map_err(|e| e.offset_by(4))?;
 
-        let chksm_bytes: Vec<u8> = chunk_type
-            .bytes()
-            .iter()
-            .copied()
-            .chain(data.iter().cloned())
-            .collect();
-        let expected_crc = crc_checksum(&chksm_bytes);
-        if expected_crc != received_crc {
-            return Err(ProgramError::TryFromError(format!(
-                "CRC mismatch: expected: {}, received: {}",
-                expected_crc, received_crc
-            )));
-        }
+        // 3. The `data` bytes
+        let crc_offset = 8 + length as usize;
+        let data = bytes
+            .get(8..crc_offset)
+            .ok_or(ProgramError::Truncated { offset: 8 })?;
+
+        // 4. The `crc` bytes
+        let crc = u32::from_be_bytes(field(crc_offset)?);
 
         Ok(Self {
             length,
             chunk_type,
             data,
-            crc: expected_crc,
+            crc,
+        })
+    }
+
+    /// Returns the `ProgramError::CrcMismatch` describing a wrong stored CRC, if any.
+    ///
+    /// The offset of the error is relative to the start of this chunk.
+    pub(crate) fn crc_error(&self) -> Option<ProgramError> {
+        let expected = checksum(&self.chunk_type, self.data);
+        (self.crc != expected).then(|| ProgramError::CrcMismatch {
+            chunk_type: self.chunk_type.to_string(),
+            expected,
+            actual: self.crc,
+            offset: 8 + self.data.len(),
         })
     }
 }
 
+impl<'a> TryFrom<&'a [u8]> for ChunkRef<'a> {
+    type Error = ProgramError;
+
+    /// Parses a chunk from the start of `bytes` without copying its data.
+    ///
+    /// Offsets carried by any returned error are relative to the start of `bytes`.
+    fn try_from(bytes: &'a [u8]) -> ProgramResult<Self> {
+        let chunk = Self::parse_unchecked(bytes)?;
+        match chunk.crc_error() {
+            Some(e) => Err(e),
+            None => Ok(chunk),
+        }
+    }
+}
+
+impl<'a> From<&'a Chunk> for ChunkRef<'a> {
+    fn from(chunk: &'a Chunk) -> Self {
+        Self {
+            length: chunk.length,
+            chunk_type: chunk.chunk_type.clone(),
+            data: &chunk.data,
+            crc: chunk.crc,
+        }
+    }
+}
+
+impl From<ChunkRef<'_>> for Chunk {
+    fn from(chunk: ChunkRef<'_>) -> Self {
+        chunk.to_chunk()
+    }
+}
+
+/// Fills `buffer` from `reader`, reporting a premature end as truncation at `offset`.
+fn read_field<R: Read>(reader: &mut R, buffer: &mut [u8], offset: usize) -> ProgramResult<()> {
+    reader.read_exact(buffer).map_err(|e| match e.kind() {
+        ErrorKind::UnexpectedEof => ProgramError::Truncated { offset },
+        _ => ProgramError::Io {
+            source: e,
+            offset: Some(offset),
+        },
+    })
+}
+
+/// Computes the CRC of a chunk over its type and data, without copying the data.
+fn checksum(chunk_type: &ChunkType, data: &[u8]) -> u32 {
+    let mut digest = PNG_CRC.digest();
+    digest.update(&chunk_type.bytes());
+    digest.update(data);
+    digest.finalize()
+}
+
 impl core::fmt::Display for Chunk {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_chunk_ref())
+    }
+}
+
+impl core::fmt::Display for ChunkRef<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         writeln!(f, "Chunk {{",)?;
         writeln!(f, "  Length: {}", self.length())?;
@@ -205,6 +405,15 @@ mod tests {
         assert_eq!(chunk_string, expected_chunk_string);
     }
 
+    #[test]
+    fn test_invalid_chunk_string() {
+        let chunk = Chunk::new(ChunkType::from_str("RuSt").unwrap(), b"ok\xffno".to_vec());
+        assert!(matches!(
+            chunk.data_as_string(),
+            Err(ProgramError::InvalidUtf8 { offset: 2 })
+        ));
+    }
+
     #[test]
     fn test_chunk_crc() {
         let chunk = testing_chunk();
@@ -256,7 +465,86 @@ mod tests {
 
         let chunk = Chunk::try_from(chunk_data.as_ref());
 
-        assert!(chunk.is_err());
+        assert!(matches!(
+            chunk,
+            Err(ProgramError::CrcMismatch {
+                expected: 2882656334,
+                actual: 2882656333,
+                offset: 50,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_repair_crc() {
+        let mut chunk_data = testing_chunk().as_bytes();
+        let last = chunk_data.len() - 1;
+        chunk_data[last] ^= 1;
+
+        let mut chunk = Chunk::parse_unchecked(&chunk_data).unwrap();
+        assert!(!chunk.has_valid_crc());
+        assert!(matches!(
+            chunk.crc_error(),
+            Some(ProgramError::CrcMismatch { offset: 50, .. })
+        ));
+        assert!(chunk.repair_crc());
+        assert!(chunk.has_valid_crc());
+        assert_eq!(chunk.crc(), 2882656334);
+        assert!(!chunk.repair_crc());
+    }
+
+    #[test]
+    fn test_chunk_ref() {
+        let bytes = testing_chunk().as_bytes();
+        let chunk_ref = ChunkRef::try_from(bytes.as_slice()).unwrap();
+        assert_eq!(chunk_ref.length(), 42);
+        assert_eq!(chunk_ref.chunk_type().to_string(), "RuSt");
+        assert_eq!(chunk_ref.data().as_ptr(), bytes[8..].as_ptr());
+        assert_eq!(chunk_ref.crc(), 2882656334);
+        assert_eq!(chunk_ref.to_chunk(), testing_chunk());
+        assert_eq!(testing_chunk().as_chunk_ref(), chunk_ref);
+
+        let mut bad_crc = bytes.clone();
+        bad_crc[50] ^= 1;
+        assert!(matches!(
+            ChunkRef::try_from(bad_crc.as_slice()),
+            Err(ProgramError::CrcMismatch { offset: 50, .. })
+        ));
+        assert!(matches!(
+            ChunkRef::try_from(&bytes[..30]),
+            Err(ProgramError::Truncated { offset: 8 })
+        ));
+    }
+
+    #[test]
+    fn test_truncated_chunk_from_bytes() {
+        let chunk_data = testing_chunk().as_bytes();
+
+        let chunk = Chunk::try_from(&chunk_data[..20]);
+        assert!(matches!(chunk, Err(ProgramError::Truncated { offset: 8 })));
+
+        let chunk = Chunk::try_from(&chunk_data[..2]);
+        assert!(matches!(chunk, Err(ProgramError::Truncated { offset: 0 })));
+    }
+
+    #[test]
+    fn test_chunk_length_overflow() {
+        let chunk_data: Vec<u8> = u32::MAX
+            .to_be_bytes()
+            .iter()
+            .chain("RuSt".as_bytes())
+            .copied()
+            .collect();
+
+        let chunk = Chunk::try_from(chunk_data.as_ref());
+        assert!(matches!(
+            chunk,
+            Err(ProgramError::LengthOverflow {
+                length: u32::MAX,
+                offset: 0
+            })
+        ));
     }
 
     #[test]
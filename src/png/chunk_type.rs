@@ -1,6 +1,10 @@
 //! Implements the `ChunkType` as described by the PNG specification.
 
-use crate::{error::ProgramError, utils::is_valid_byte, ProgramResult};
+use crate::{
+    error::{ChunkTypeError, ProgramError},
+    utils::is_valid_byte,
+    ProgramResult,
+};
 
 /// A validated PNG chunk type. See the PNG spec for more details.
 /// http://www.libpng.org/pub/png/spec/1.2/PNG-Structure.html
@@ -60,13 +64,21 @@ impl TryFrom<[u8; 4]> for ChunkType {
     type Error = ProgramError;
 
     fn try_from(value: [u8; 4]) -> ProgramResult<Self> {
-        if value.is_empty() {
-            Err(ProgramError::TryFromError(
-                "Chunk type code cannot be empty".to_string(),
-            ))
-        } else {
-            Ok(Self { type_code: value })
+        if let Some(index) = value.iter().position(|b| !is_valid_byte(*b)) {
+            return Err(invalid_chunk_type(
+                &value,
+                ChunkTypeError::InvalidByte {
+                    byte: value[index],
+                    index,
+                },
+            ));
         }
+
+        let chunk_type = Self { type_code: value };
+        if !chunk_type.is_reserved_bit_valid() {
+            return Err(invalid_chunk_type(&value, ChunkTypeError::ReservedBit));
+        }
+        Ok(chunk_type)
     }
 }
 
@@ -74,21 +86,25 @@ impl core::str::FromStr for ChunkType {
     type Err = ProgramError;
 
     fn from_str(s: &str) -> ProgramResult<Self> {
-        let mut bytes = [0u8; 4];
-        for (idx, byte) in s.as_bytes().iter().enumerate() {
-            bytes[idx] = *byte;
-        }
-        Ok(Self { type_code: bytes })
+        let bytes: [u8; 4] = s
+            .as_bytes()
+            .try_into()
+            .map_err(|_| invalid_chunk_type(s.as_bytes(), ChunkTypeError::Length(s.len())))?;
+        Self::try_from(bytes)
+    }
+}
+
+fn invalid_chunk_type(code: &[u8], reason: ChunkTypeError) -> ProgramError {
+    ProgramError::InvalidChunkType {
+        code: String::from_utf8_lossy(code).into_owned(),
+        reason,
+        offset: None,
     }
 }
 
 impl core::fmt::Display for ChunkType {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let tc_str = if let Ok(res) = std::str::from_utf8(&self.type_code) {
-            res
-        } else {
-            "Utf8Error: Invalid UTF-8"
-        };
+        let tc_str = std::str::from_utf8(&self.type_code).unwrap_or("Utf8Error: Invalid UTF-8");
         write!(f, "{}", tc_str)
     }
 }
@@ -146,8 +162,14 @@ mod tests {
 
     #[test]
     pub fn test_chunk_type_is_reserved_bit_invalid() {
-        let chunk = ChunkType::from_str("Rust").unwrap();
-        assert!(!chunk.is_reserved_bit_valid());
+        let chunk = ChunkType::from_str("Rust");
+        assert!(matches!(
+            chunk,
+            Err(ProgramError::InvalidChunkType {
+                reason: ChunkTypeError::ReservedBit,
+                ..
+            })
+        ));
     }
 
     #[test]
@@ -169,13 +191,53 @@ mod tests {
     }
 
     #[test]
-    pub fn test_invalid_chunk_is_valid() {
-        let chunk = ChunkType::from_str("Rust").unwrap();
-        assert!(!chunk.is_valid());
+    pub fn test_invalid_chunk_is_rejected() {
+        let chunk = ChunkType::from_str("Rust");
+        assert!(chunk.is_err());
 
         let chunk = ChunkType::from_str("Ru1t");
-        assert!(chunk.is_ok());
-        assert!(!chunk.unwrap().is_valid());
+        assert!(matches!(
+            chunk,
+            Err(ProgramError::InvalidChunkType {
+                reason: ChunkTypeError::InvalidByte {
+                    byte: b'1',
+                    index: 2
+                },
+                offset: None,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    pub fn test_chunk_type_from_str_wrong_length() {
+        assert!(matches!(
+            ChunkType::from_str("RuStY"),
+            Err(ProgramError::InvalidChunkType {
+                reason: ChunkTypeError::Length(5),
+                ..
+            })
+        ));
+        assert!(matches!(
+            ChunkType::from_str("RuS"),
+            Err(ProgramError::InvalidChunkType {
+                reason: ChunkTypeError::Length(3),
+                ..
+            })
+        ));
+        assert!(matches!(
+            ChunkType::from_str(""),
+            Err(ProgramError::InvalidChunkType {
+                reason: ChunkTypeError::Length(0),
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    pub fn test_chunk_type_from_invalid_bytes() {
+        assert!(ChunkType::try_from([82, 117, 0, 116]).is_err());
+        assert!(ChunkType::try_from([82, 117, 115, 116]).is_err());
     }
 
     #[test]
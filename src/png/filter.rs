@@ -0,0 +1,215 @@
+//! Implements the scanline filters described by the PNG specification.
+//! http://www.libpng.org/pub/png/spec/1.2/PNG-Filters.html
+
+use crate::{
+    error::{ImageDataError, ProgramError},
+    ProgramResult,
+};
+
+/// The five filter types of filter method `0`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterType {
+    /// The scanline is stored unmodified
+    None,
+    /// Each byte is stored as the difference from the byte one pixel to the left
+    Sub,
+    /// Each byte is stored as the difference from the byte above
+    Up,
+    /// Each byte is stored as the difference from the average of the left and above bytes
+    Average,
+    /// Each byte is stored as the difference from the Paeth predictor of its neighbours
+    Paeth,
+}
+
+impl FilterType {
+    /// All filter types, in order of their codes
+    pub const ALL: [Self; 5] = [Self::None, Self::Sub, Self::Up, Self::Average, Self::Paeth];
+
+    /// The filter type byte that precedes a scanline filtered with this type
+    pub fn code(&self) -> u8 {
+        match self {
+            Self::None => 0,
+            Self::Sub => 1,
+            Self::Up => 2,
+            Self::Average => 3,
+            Self::Paeth => 4,
+        }
+    }
+}
+
+impl TryFrom<u8> for FilterType {
+    type Error = ProgramError;
+
+    fn try_from(code: u8) -> ProgramResult<Self> {
+        match code {
+            0 => Ok(Self::None),
+            1 => Ok(Self::Sub),
+            2 => Ok(Self::Up),
+            3 => Ok(Self::Average),
+            4 => Ok(Self::Paeth),
+            _ => Err(ProgramError::InvalidImageData(ImageDataError::FilterType(
+                code,
+            ))),
+        }
+    }
+}
+
+/// The Paeth predictor: whichever of left (`a`), above (`b`) and upper left (`c`)
+/// is closest to `a + b - c`.
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = (
+        (p - a as i16).abs(),
+        (p - b as i16).abs(),
+        (p - c as i16).abs(),
+    );
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+/// Applies `filter` to `row` and appends the filter type byte and filtered bytes to `out`.
+///
+/// `prior` is the unfiltered previous row (all zeros for the first row) and
+/// `bpp` the number of bytes per complete pixel, rounded up to one.
+pub fn filter(filter: FilterType, row: &[u8], prior: &[u8], bpp: usize, out: &mut Vec<u8>) {
+    out.push(filter.code());
+    for i in 0..row.len() {
+        let (a, c) = if i >= bpp {
+            (row[i - bpp], prior[i - bpp])
+        } else {
+            (0, 0)
+        };
+        let b = prior[i];
+        let predictor = match filter {
+            FilterType::None => 0,
+            FilterType::Sub => a,
+            FilterType::Up => b,
+            FilterType::Average => ((a as u16 + b as u16) / 2) as u8,
+            FilterType::Paeth => paeth(a, b, c),
+        };
+        out.push(row[i].wrapping_sub(predictor));
+    }
+}
+
+/// Picks a filter for `row` with the minimum sum of absolute differences heuristic
+/// recommended by the PNG spec: the filter whose output, read as signed bytes,
+/// has the smallest sum of magnitudes.
+pub fn best_filter(row: &[u8], prior: &[u8], bpp: usize) -> FilterType {
+    let mut out = Vec::with_capacity(row.len() + 1);
+    FilterType::ALL
+        .into_iter()
+        .min_by_key(|f| {
+            out.clear();
+            filter(*f, row, prior, bpp, &mut out);
+            out[1..]
+                .iter()
+                .map(|b| (*b as i8).unsigned_abs() as u64)
+                .sum::<u64>()
+        })
+        .unwrap_or(FilterType::None)
+}
+
+/// Reverses `filter` on `row` in place.
+///
+/// `prior` is the already unfiltered previous row (all zeros for the first row) and
+/// `bpp` the number of bytes per complete pixel, rounded up to one.
+pub fn unfilter(filter: FilterType, row: &mut [u8], prior: &[u8], bpp: usize) {
+    match filter {
+        FilterType::None => {}
+        FilterType::Sub => {
+            for i in bpp..row.len() {
+                row[i] = row[i].wrapping_add(row[i - bpp]);
+            }
+        }
+        FilterType::Up => {
+            for (x, b) in row.iter_mut().zip(prior) {
+                *x = x.wrapping_add(*b);
+            }
+        }
+        FilterType::Average => {
+            for i in 0..row.len() {
+                let a = if i >= bpp { row[i - bpp] } else { 0 };
+                let avg = ((a as u16 + prior[i] as u16) / 2) as u8;
+                row[i] = row[i].wrapping_add(avg);
+            }
+        }
+        FilterType::Paeth => {
+            for i in 0..row.len() {
+                let (a, c) = if i >= bpp {
+                    (row[i - bpp], prior[i - bpp])
+                } else {
+                    (0, 0)
+                };
+                row[i] = row[i].wrapping_add(paeth(a, prior[i], c));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unfilter_sub() {
+        let mut row = [1, 2, 3, 4];
+        unfilter(FilterType::Sub, &mut row, &[0; 4], 2);
+        assert_eq!(row, [1, 2, 4, 6]);
+    }
+
+    #[test]
+    fn test_unfilter_up() {
+        let mut row = [1, 2, 3, 255];
+        unfilter(FilterType::Up, &mut row, &[10, 20, 30, 2], 1);
+        assert_eq!(row, [11, 22, 33, 1]);
+    }
+
+    #[test]
+    fn test_unfilter_average() {
+        let mut row = [1, 2, 3];
+        unfilter(FilterType::Average, &mut row, &[10, 20, 30], 1);
+        // 1 + 10/2, 2 + (6+20)/2, 3 + (15+30)/2
+        assert_eq!(row, [6, 15, 25]);
+    }
+
+    #[test]
+    fn test_paeth_predictor() {
+        assert_eq!(paeth(10, 20, 10), 20);
+        assert_eq!(paeth(20, 10, 10), 20);
+        assert_eq!(paeth(10, 10, 20), 10);
+    }
+
+    #[test]
+    fn test_filter_round_trip() {
+        let prior = [7, 200, 13, 90, 45, 3];
+        let row = [12, 250, 1, 77, 128, 64];
+        for filter_type in FilterType::ALL {
+            let mut out = Vec::new();
+            filter(filter_type, &row, &prior, 2, &mut out);
+            assert_eq!(out[0], filter_type.code());
+
+            let mut unfiltered = out[1..].to_vec();
+            unfilter(filter_type, &mut unfiltered, &prior, 2);
+            assert_eq!(unfiltered, row);
+        }
+    }
+
+    #[test]
+    fn test_best_filter() {
+        // A smooth gradient is cheapest as differences from the left neighbour
+        let row: Vec<u8> = (0..32).map(|i| i * 3).collect();
+        assert_eq!(best_filter(&row, &[0; 32], 1), FilterType::Sub);
+        // A row identical to the one above is cheapest as differences from above
+        assert_eq!(best_filter(&row, &row, 1), FilterType::Up);
+    }
+
+    #[test]
+    fn test_unknown_filter_type() {
+        assert!(FilterType::try_from(5).is_err());
+    }
+}
@@ -0,0 +1,374 @@
+//! Implements `ImageHeader`, the typed contents of the `IHDR` chunk.
+
+use super::chunk::{Chunk, ChunkRef};
+use crate::{
+    error::{ImageHeaderError, ProgramError},
+    ProgramResult,
+};
+
+/// The color type of an image as described by the PNG spec.
+/// http://www.libpng.org/pub/png/spec/1.2/PNG-Chunks.html#C.IHDR
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorType {
+    /// Each pixel is a grayscale sample
+    Grayscale,
+    /// Each pixel is an R,G,B triple
+    Rgb,
+    /// Each pixel is a palette index; a `PLTE` chunk must appear
+    Indexed,
+    /// Each pixel is a grayscale sample followed by an alpha sample
+    GrayscaleAlpha,
+    /// Each pixel is an R,G,B triple followed by an alpha sample
+    Rgba,
+}
+
+impl ColorType {
+    /// The color type code stored in the `IHDR` chunk
+    pub fn code(&self) -> u8 {
+        match self {
+            Self::Grayscale => 0,
+            Self::Rgb => 2,
+            Self::Indexed => 3,
+            Self::GrayscaleAlpha => 4,
+            Self::Rgba => 6,
+        }
+    }
+
+    /// The number of samples in each pixel
+    pub fn channels(&self) -> usize {
+        match self {
+            Self::Grayscale | Self::Indexed => 1,
+            Self::GrayscaleAlpha => 2,
+            Self::Rgb => 3,
+            Self::Rgba => 4,
+        }
+    }
+
+    /// The bit depths the PNG spec allows for this color type
+    pub fn allowed_bit_depths(&self) -> &'static [u8] {
+        match self {
+            Self::Grayscale => &[1, 2, 4, 8, 16],
+            Self::Indexed => &[1, 2, 4, 8],
+            Self::Rgb | Self::GrayscaleAlpha | Self::Rgba => &[8, 16],
+        }
+    }
+}
+
+impl TryFrom<u8> for ColorType {
+    type Error = ProgramError;
+
+    fn try_from(code: u8) -> ProgramResult<Self> {
+        match code {
+            0 => Ok(Self::Grayscale),
+            2 => Ok(Self::Rgb),
+            3 => Ok(Self::Indexed),
+            4 => Ok(Self::GrayscaleAlpha),
+            6 => Ok(Self::Rgba),
+            _ => Err(ProgramError::InvalidImageHeader(
+                ImageHeaderError::ColorType(code),
+            )),
+        }
+    }
+}
+
+impl core::fmt::Display for ColorType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::Grayscale => "Grayscale",
+            Self::Rgb => "RGB",
+            Self::Indexed => "Indexed",
+            Self::GrayscaleAlpha => "Grayscale + alpha",
+            Self::Rgba => "RGBA",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// The validated contents of an `IHDR` chunk.
+/// http://www.libpng.org/pub/png/spec/1.2/PNG-Chunks.html#C.IHDR
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageHeader {
+    width: u32,
+    height: u32,
+    bit_depth: u8,
+    color_type: ColorType,
+    compression_method: u8,
+    filter_method: u8,
+    interlace_method: u8,
+}
+
+impl ImageHeader {
+    /// Length of the `IHDR` chunk data in bytes
+    pub const LENGTH: usize = 13;
+
+    /// Width of the image in pixels
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    /// Height of the image in pixels
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Number of bits per sample, or per palette index for indexed images
+    pub fn bit_depth(&self) -> u8 {
+        self.bit_depth
+    }
+
+    /// The `ColorType` of the image
+    pub fn color_type(&self) -> ColorType {
+        self.color_type
+    }
+
+    /// The compression method; `0` (deflate) is the only one defined
+    pub fn compression_method(&self) -> u8 {
+        self.compression_method
+    }
+
+    /// The filter method; `0` (adaptive filtering) is the only one defined
+    pub fn filter_method(&self) -> u8 {
+        self.filter_method
+    }
+
+    /// The interlace method: `0` for none, `1` for Adam7
+    pub fn interlace_method(&self) -> u8 {
+        self.interlace_method
+    }
+
+    /// Returns true if the image is Adam7-interlaced
+    pub fn is_interlaced(&self) -> bool {
+        self.interlace_method == 1
+    }
+
+    /// Returns a copy of this header with the interlace method set to Adam7 or none
+    pub fn with_interlace(&self, interlaced: bool) -> Self {
+        Self {
+            interlace_method: interlaced as u8,
+            ..*self
+        }
+    }
+
+    /// Returns a non-interlaced copy of this header with the given dimensions.
+    ///
+    /// Used to describe the reduced images of Adam7 passes.
+    pub(crate) fn with_size(&self, width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            interlace_method: 0,
+            ..*self
+        }
+    }
+
+    /// Returns this header as the 13 data bytes of an `IHDR` chunk
+    pub fn as_bytes(&self) -> Vec<u8> {
+        self.width
+            .to_be_bytes()
+            .iter()
+            .chain(self.height.to_be_bytes().iter())
+            .chain(
+                [
+                    self.bit_depth,
+                    self.color_type.code(),
+                    self.compression_method,
+                    self.filter_method,
+                    self.interlace_method,
+                ]
+                .iter(),
+            )
+            .copied()
+            .collect()
+    }
+
+    /// The number of bits in each pixel
+    pub fn bits_per_pixel(&self) -> usize {
+        self.color_type.channels() * self.bit_depth as usize
+    }
+
+    /// The number of bytes in an unfiltered scanline `width` pixels wide
+    pub fn row_bytes(&self, width: u32) -> usize {
+        (width as usize * self.bits_per_pixel()).div_ceil(8)
+    }
+
+    /// The byte distance used by the scanline filters: bytes per complete pixel, rounded up to one
+    pub fn filter_bpp(&self) -> usize {
+        (self.bits_per_pixel() / 8).max(1)
+    }
+}
+
+impl TryFrom<&[u8]> for ImageHeader {
+    type Error = ProgramError;
+
+    fn try_from(data: &[u8]) -> ProgramResult<Self> {
+        if data.len() != Self::LENGTH {
+            return Err(ProgramError::InvalidImageHeader(ImageHeaderError::Length(
+                data.len(),
+            )));
+        }
+
+        let width = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+        let height = u32::from_be_bytes([data[4], data[5], data[6], data[7]]);
+        if width == 0
+            || height == 0
+            || width > super::chunk::MAX_LEN
+            || height > super::chunk::MAX_LEN
+        {
+            return Err(ProgramError::InvalidImageHeader(
+                ImageHeaderError::Dimensions { width, height },
+            ));
+        }
+
+        let bit_depth = data[8];
+        let color_type = ColorType::try_from(data[9])?;
+        if !color_type.allowed_bit_depths().contains(&bit_depth) {
+            return Err(ProgramError::InvalidImageHeader(
+                ImageHeaderError::BitDepth {
+                    bit_depth,
+                    color_type,
+                },
+            ));
+        }
+
+        let (compression_method, filter_method, interlace_method) = (data[10], data[11], data[12]);
+        if compression_method != 0 {
+            return Err(ProgramError::InvalidImageHeader(
+                ImageHeaderError::CompressionMethod(compression_method),
+            ));
+        }
+        if filter_method != 0 {
+            return Err(ProgramError::InvalidImageHeader(
+                ImageHeaderError::FilterMethod(filter_method),
+            ));
+        }
+        if interlace_method > 1 {
+            return Err(ProgramError::InvalidImageHeader(
+                ImageHeaderError::InterlaceMethod(interlace_method),
+            ));
+        }
+
+        Ok(Self {
+            width,
+            height,
+            bit_depth,
+            color_type,
+            compression_method,
+            filter_method,
+            interlace_method,
+        })
+    }
+}
+
+impl TryFrom<&Chunk> for ImageHeader {
+    type Error = ProgramError;
+
+    fn try_from(chunk: &Chunk) -> ProgramResult<Self> {
+        Self::try_from(&chunk.as_chunk_ref())
+    }
+}
+
+impl TryFrom<&ChunkRef<'_>> for ImageHeader {
+    type Error = ProgramError;
+
+    fn try_from(chunk: &ChunkRef<'_>) -> ProgramResult<Self> {
+        if chunk.chunk_type().bytes() != *b"IHDR" {
+            return Err(ProgramError::InvalidImageHeader(
+                ImageHeaderError::NotIhdr {
+                    chunk_type: chunk.chunk_type().to_string(),
+                },
+            ));
+        }
+        Self::try_from(chunk.data())
+    }
+}
+
+impl core::fmt::Display for ImageHeader {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "ImageHeader {{",)?;
+        writeln!(f, "  Dimensions: {}x{} pixels", self.width(), self.height())?;
+        writeln!(f, "  Bit depth: {}", self.bit_depth())?;
+        writeln!(
+            f,
+            "  Color type: {} ({})",
+            self.color_type(),
+            self.color_type().code()
+        )?;
+        writeln!(f, "  Compression: deflate ({})", self.compression_method())?;
+        writeln!(f, "  Filter: adaptive ({})", self.filter_method())?;
+        let interlace = if self.is_interlaced() {
+            "Adam7"
+        } else {
+            "none"
+        };
+        writeln!(
+            f,
+            "  Interlace: {} ({})",
+            interlace,
+            self.interlace_method()
+        )?;
+        writeln!(f, "}}",)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ihdr_data(width: u32, height: u32, bit_depth: u8, color_type: u8) -> Vec<u8> {
+        width
+            .to_be_bytes()
+            .iter()
+            .chain(height.to_be_bytes().iter())
+            .chain([bit_depth, color_type, 0, 0, 0].iter())
+            .copied()
+            .collect()
+    }
+
+    #[test]
+    fn test_valid_header() {
+        let header = ImageHeader::try_from(ihdr_data(50, 40, 8, 6).as_slice()).unwrap();
+        assert_eq!(header.width(), 50);
+        assert_eq!(header.height(), 40);
+        assert_eq!(header.bit_depth(), 8);
+        assert_eq!(header.color_type(), ColorType::Rgba);
+        assert!(!header.is_interlaced());
+    }
+
+    #[test]
+    fn test_header_as_bytes() {
+        let data = ihdr_data(50, 40, 8, 6);
+        let header = ImageHeader::try_from(data.as_slice()).unwrap();
+        assert_eq!(header.as_bytes(), data);
+        assert_eq!(header.with_interlace(true).as_bytes()[12], 1);
+    }
+
+    #[test]
+    fn test_invalid_bit_depth_for_color_type() {
+        assert!(ImageHeader::try_from(ihdr_data(50, 40, 4, 2).as_slice()).is_err());
+        assert!(ImageHeader::try_from(ihdr_data(50, 40, 16, 3).as_slice()).is_err());
+        assert!(ImageHeader::try_from(ihdr_data(50, 40, 1, 0).as_slice()).is_ok());
+    }
+
+    #[test]
+    fn test_invalid_color_type() {
+        assert!(ImageHeader::try_from(ihdr_data(50, 40, 8, 5).as_slice()).is_err());
+    }
+
+    #[test]
+    fn test_invalid_dimensions() {
+        assert!(ImageHeader::try_from(ihdr_data(0, 40, 8, 6).as_slice()).is_err());
+    }
+
+    #[test]
+    fn test_invalid_length() {
+        assert!(ImageHeader::try_from(&ihdr_data(50, 40, 8, 6)[..12]).is_err());
+    }
+
+    #[test]
+    fn test_invalid_interlace_method() {
+        let mut data = ihdr_data(50, 40, 8, 6);
+        data[12] = 2;
+        assert!(ImageHeader::try_from(data.as_slice()).is_err());
+    }
+}
@@ -0,0 +1,604 @@
+//! Implements least-significant-bit (LSB) embedding of payloads into pixel samples.
+
+use super::{
+    header::{ColorType, ImageHeader},
+    pixels::Pixels,
+};
+use crate::{
+    crypto::{self, KdfParams},
+    error::{ArgumentError, ImageDataError, PayloadLocation, ProgramError, UnsupportedFeature},
+    ProgramResult,
+};
+use rand::Rng;
+use rand_chacha::{rand_core::SeedableRng, ChaCha20Rng};
+use std::collections::HashMap;
+
+/// Marks the start of an LSB payload
+pub const MAGIC: [u8; 4] = *b"imlS";
+
+/// Length of the header written before the payload: magic bytes and a 4-byte length
+pub const HEADER_LEN: usize = 8;
+
+/// The color channels whose samples carry payload bits.
+///
+/// For grayscale images the red, green and blue channels all select the gray sample.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Channels {
+    /// Use the red (or gray) samples
+    pub red: bool,
+    /// Use the green (or gray) samples
+    pub green: bool,
+    /// Use the blue (or gray) samples
+    pub blue: bool,
+    /// Use the alpha samples
+    pub alpha: bool,
+}
+
+impl Default for Channels {
+    /// Red, green and blue, leaving alpha untouched
+    fn default() -> Self {
+        Self {
+            red: true,
+            green: true,
+            blue: true,
+            alpha: false,
+        }
+    }
+}
+
+impl Channels {
+    /// Returns the sample indices selected for pixels of `color_type`.
+    fn sample_indices(&self, color_type: ColorType) -> ProgramResult<Vec<usize>> {
+        let color = self.red || self.green || self.blue;
+        let indices = match color_type {
+            ColorType::Grayscale => [color.then_some(0)].to_vec(),
+            ColorType::GrayscaleAlpha => [color.then_some(0), self.alpha.then_some(1)].to_vec(),
+            ColorType::Rgb => [
+                self.red.then_some(0),
+                self.green.then_some(1),
+                self.blue.then_some(2),
+            ]
+            .to_vec(),
+            ColorType::Rgba => [
+                self.red.then_some(0),
+                self.green.then_some(1),
+                self.blue.then_some(2),
+                self.alpha.then_some(3),
+            ]
+            .to_vec(),
+            ColorType::Indexed => {
+                return Err(ProgramError::Unsupported(UnsupportedFeature::LsbIndexed))
+            }
+        };
+
+        let indices: Vec<usize> = indices.into_iter().flatten().collect();
+        if indices.is_empty() {
+            return Err(ProgramError::InvalidArgument(
+                ArgumentError::MissingChannels(color_type),
+            ));
+        }
+        Ok(indices)
+    }
+}
+
+impl core::str::FromStr for Channels {
+    type Err = ProgramError;
+
+    /// Parses a set of channel letters such as `rgb` or `RGBA`
+    fn from_str(s: &str) -> ProgramResult<Self> {
+        let mut channels = Self {
+            red: false,
+            green: false,
+            blue: false,
+            alpha: false,
+        };
+        for c in s.chars() {
+            match c.to_ascii_lowercase() {
+                'r' => channels.red = true,
+                'g' => channels.green = true,
+                'b' => channels.blue = true,
+                'a' => channels.alpha = true,
+                _ => return Err(ProgramError::InvalidArgument(ArgumentError::Channel(c))),
+            }
+        }
+        if s.is_empty() {
+            return Err(ProgramError::InvalidArgument(ArgumentError::NoChannels));
+        }
+        Ok(channels)
+    }
+}
+
+/// Options controlling where payload bits are written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LsbOptions {
+    /// Number of low bits of each selected sample that carry payload bits, from 1 to 4
+    pub bits_per_channel: u8,
+    /// The channels whose samples carry payload bits
+    pub channels: Channels,
+}
+
+impl Default for LsbOptions {
+    fn default() -> Self {
+        Self {
+            bits_per_channel: 1,
+            channels: Channels::default(),
+        }
+    }
+}
+
+/// Salt for deriving scatter keys; the key is never stored, so it cannot be random
+const SCATTER_SALT: &[u8] = b"imagico lsb scatter";
+
+/// A secret key that selects the pseudo-random order in which samples carry payload bits
+#[derive(Clone, PartialEq, Eq)]
+pub struct ScatterKey([u8; 32]);
+
+impl ScatterKey {
+    /// Derives a key from `passphrase` with the default `KdfParams`.
+    pub fn from_passphrase(passphrase: &[u8]) -> ProgramResult<Self> {
+        Self::from_passphrase_with_params(passphrase, KdfParams::default())
+    }
+
+    /// Derives a key from `passphrase` with Argon2id and the given cost parameters.
+    pub fn from_passphrase_with_params(
+        passphrase: &[u8],
+        params: KdfParams,
+    ) -> ProgramResult<Self> {
+        Ok(Self(crypto::derive_key(passphrase, SCATTER_SALT, params)?))
+    }
+}
+
+impl From<[u8; 32]> for ScatterKey {
+    fn from(bytes: [u8; 32]) -> Self {
+        Self(bytes)
+    }
+}
+
+impl std::fmt::Debug for ScatterKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ScatterKey(..)")
+    }
+}
+
+/// Draws slot indices from a Fisher-Yates shuffle of all slots seeded by a `ScatterKey`.
+///
+/// Only as much of the shuffle as has been asked for is computed, so embedding a
+/// short payload into a large image stays cheap.
+struct Scatter {
+    rng: ChaCha20Rng,
+    count: usize,
+    swapped: HashMap<usize, usize>,
+    order: Vec<usize>,
+}
+
+impl Scatter {
+    fn new(key: &ScatterKey, count: usize) -> Self {
+        Self {
+            rng: ChaCha20Rng::from_seed(key.0),
+            count,
+            swapped: HashMap::new(),
+            order: Vec::new(),
+        }
+    }
+
+    /// Returns the slot visited at step `i`, which must be less than the number of slots
+    fn get(&mut self, i: usize) -> usize {
+        while self.order.len() <= i {
+            let n = self.order.len();
+            let j = self.rng.gen_range(n..self.count);
+            let at_j = self.swapped.get(&j).copied().unwrap_or(j);
+            let at_n = self.swapped.remove(&n).unwrap_or(n);
+            let _ = self.swapped.insert(j, at_n);
+            self.order.push(at_j);
+        }
+        self.order[i]
+    }
+}
+
+/// The sample positions of an image that can carry payload bits
+struct Slots {
+    width: u32,
+    indices: Vec<usize>,
+    bits: usize,
+    count: usize,
+}
+
+impl Slots {
+    fn new(header: &ImageHeader, options: &LsbOptions) -> ProgramResult<Self> {
+        let bits = options.bits_per_channel;
+        if !(1..=4).contains(&bits) || bits > header.bit_depth() {
+            return Err(ProgramError::InvalidArgument(ArgumentError::Bits {
+                bits,
+                max: header.bit_depth().min(4),
+            }));
+        }
+        let indices = options.channels.sample_indices(header.color_type())?;
+        // Header dimensions are untrusted, so every product up to `capacity` is checked
+        let count = (header.width() as usize)
+            .checked_mul(header.height() as usize)
+            .and_then(|pixels| pixels.checked_mul(indices.len()))
+            .filter(|count| count.checked_mul(bits as usize).is_some())
+            .ok_or(ProgramError::InvalidImageData(ImageDataError::Samples {
+                width: header.width(),
+                height: header.height(),
+            }))?;
+        Ok(Self {
+            width: header.width(),
+            indices,
+            bits: bits as usize,
+            count,
+        })
+    }
+
+    /// Returns the pixel and channel of slot `i`
+    fn position(&self, i: usize) -> (u32, u32, usize) {
+        let pixel = i / self.indices.len();
+        (
+            (pixel % self.width as usize) as u32,
+            (pixel / self.width as usize) as u32,
+            self.indices[i % self.indices.len()],
+        )
+    }
+
+    /// Number of payload bytes that fit after the header
+    ///
+    /// `Slots::new` has checked that `count * bits` fits in a `usize`.
+    fn capacity(&self) -> usize {
+        (self.count * self.bits / 8).saturating_sub(HEADER_LEN)
+    }
+}
+
+/// Returns the largest payload, in bytes, that `embed` can fit into an image described by `header`.
+pub fn capacity(header: &ImageHeader, options: &LsbOptions) -> ProgramResult<usize> {
+    Ok(Slots::new(header, options)?.capacity())
+}
+
+/// Writes `payload` into the low bits of the selected samples of `pixels`, pixel by pixel.
+///
+/// The payload is preceded by a header holding the magic bytes `imlS` and the payload length.
+/// This function returns `ProgramError::CapacityExceeded` if the payload does not fit.
+pub fn embed(pixels: &mut Pixels, payload: &[u8], options: &LsbOptions) -> ProgramResult<()> {
+    let slots = Slots::new(pixels.header(), options)?;
+    embed_in_order(pixels, payload, &slots, |i| i)
+}
+
+/// Reads a payload written by `embed` with the same `options`.
+///
+/// This function returns `ProgramError::PayloadNotFound` if no LSB header is present.
+pub fn extract(pixels: &Pixels, options: &LsbOptions) -> ProgramResult<Vec<u8>> {
+    let slots = Slots::new(pixels.header(), options)?;
+    extract_in_order(pixels, &slots, |i| i)
+}
+
+/// Like `embed`, but spreads the payload bits over the image in a pseudo-random order
+/// chosen by `key`.
+///
+/// Without the key the payload cannot be located, and reading it sequentially gives noise.
+pub fn embed_scattered(
+    pixels: &mut Pixels,
+    payload: &[u8],
+    options: &LsbOptions,
+    key: &ScatterKey,
+) -> ProgramResult<()> {
+    let slots = Slots::new(pixels.header(), options)?;
+    let mut scatter = Scatter::new(key, slots.count);
+    embed_in_order(pixels, payload, &slots, |i| scatter.get(i))
+}
+
+/// Reads a payload written by `embed_scattered` with the same `options` and `key`.
+///
+/// This function returns `ProgramError::PayloadNotFound` if the key is wrong or no payload is present.
+pub fn extract_scattered(
+    pixels: &Pixels,
+    options: &LsbOptions,
+    key: &ScatterKey,
+) -> ProgramResult<Vec<u8>> {
+    let slots = Slots::new(pixels.header(), options)?;
+    let mut scatter = Scatter::new(key, slots.count);
+    extract_in_order(pixels, &slots, |i| scatter.get(i))
+}
+
+/// Writes the header and `payload` into the slots visited in the order given by `order`.
+fn embed_in_order(
+    pixels: &mut Pixels,
+    payload: &[u8],
+    slots: &Slots,
+    mut order: impl FnMut(usize) -> usize,
+) -> ProgramResult<()> {
+    // `capacity` saturates at zero, so check that the header itself fits as well
+    let fits = slots.count * slots.bits / 8 >= HEADER_LEN + payload.len();
+    if !fits || payload.len() > u32::MAX as usize {
+        return Err(ProgramError::CapacityExceeded {
+            needed: payload.len(),
+            available: slots.capacity(),
+        });
+    }
+
+    let framed: Vec<u8> = MAGIC
+        .iter()
+        .chain((payload.len() as u32).to_be_bytes().iter())
+        .chain(payload.iter())
+        .copied()
+        .collect();
+    let mask = (1u16 << slots.bits) - 1;
+    for (i, value) in bit_groups(&framed, slots.bits).enumerate() {
+        let (x, y, channel) = slots.position(order(i));
+        let sample = pixels.sample(x, y, channel);
+        pixels.set_sample(x, y, channel, (sample & !mask) | value);
+    }
+    Ok(())
+}
+
+/// Reads the header and payload from the slots visited in the order given by `order`.
+fn extract_in_order(
+    pixels: &Pixels,
+    slots: &Slots,
+    mut order: impl FnMut(usize) -> usize,
+) -> ProgramResult<Vec<u8>> {
+    let mut read = |bytes: usize| -> Vec<u8> {
+        let groups = (bytes * 8).div_ceil(slots.bits);
+        let values = (0..groups).map(|i| {
+            let (x, y, channel) = slots.position(order(i));
+            pixels.sample(x, y, channel) & ((1 << slots.bits) - 1)
+        });
+        from_bit_groups(values, slots.bits, bytes)
+    };
+
+    let not_found = || ProgramError::PayloadNotFound(PayloadLocation::Lsb);
+    if slots.count * slots.bits / 8 < HEADER_LEN {
+        return Err(not_found());
+    }
+    let header = read(HEADER_LEN);
+    if header[..4] != MAGIC {
+        return Err(not_found());
+    }
+    let len = u32::from_be_bytes([header[4], header[5], header[6], header[7]]) as usize;
+    if len > slots.capacity() {
+        return Err(not_found());
+    }
+    Ok(read(HEADER_LEN + len).split_off(HEADER_LEN))
+}
+
+/// Splits `bytes` into groups of `bits` bits, most significant bit first.
+/// The last group is padded with zero bits.
+fn bit_groups(bytes: &[u8], bits: usize) -> impl Iterator<Item = u16> + '_ {
+    let total = bytes.len() * 8;
+    (0..total.div_ceil(bits)).map(move |group| {
+        (0..bits).fold(0u16, |value, b| {
+            let bit = group * bits + b;
+            let set = bit < total && bytes[bit / 8] & (0x80 >> (bit % 8)) != 0;
+            (value << 1) | set as u16
+        })
+    })
+}
+
+/// Joins groups of `bits` bits, most significant bit first, into `len` bytes.
+fn from_bit_groups(values: impl Iterator<Item = u16>, bits: usize, len: usize) -> Vec<u8> {
+    let mut bytes = vec![0u8; len];
+    let mut bit = 0;
+    for value in values {
+        for b in (0..bits).rev() {
+            if bit < len * 8 && value & (1 << b) != 0 {
+                bytes[bit / 8] |= 0x80 >> (bit % 8);
+            }
+            bit += 1;
+        }
+    }
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::str::FromStr;
+
+    fn pixels(width: u32, height: u32, bit_depth: u8, color_type: u8) -> Pixels {
+        let data: Vec<u8> = width
+            .to_be_bytes()
+            .iter()
+            .chain(height.to_be_bytes().iter())
+            .chain([bit_depth, color_type, 0, 0, 0].iter())
+            .copied()
+            .collect();
+        let header = ImageHeader::try_from(data.as_slice()).unwrap();
+        let len = header.row_bytes(width) * height as usize;
+        let data = (0..len).map(|i| (i * 37 % 251) as u8).collect();
+        Pixels::from_raw(header, None, data).unwrap()
+    }
+
+    const MESSAGE: &[u8] = b"This is where your secret message will be!";
+
+    #[test]
+    fn test_embed_extract() {
+        for bits in 1..=4 {
+            let options = LsbOptions {
+                bits_per_channel: bits,
+                ..LsbOptions::default()
+            };
+            let mut image = pixels(20, 20, 8, 6);
+            let original = image.clone();
+            embed(&mut image, MESSAGE, &options).unwrap();
+            assert_eq!(extract(&image, &options).unwrap(), MESSAGE);
+
+            // Only the selected low bits change, and alpha is untouched
+            for y in 0..20 {
+                for x in 0..20 {
+                    let (a, b) = (original.pixel(x, y), image.pixel(x, y));
+                    assert!(a.iter().zip(&b).all(|(a, b)| a >> bits == b >> bits));
+                    assert_eq!(a[3], b[3]);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_embed_sixteen_bit_grayscale_alpha() {
+        let options = LsbOptions {
+            bits_per_channel: 2,
+            channels: Channels::from_str("ga").unwrap(),
+        };
+        let mut image = pixels(16, 16, 16, 4);
+        embed(&mut image, MESSAGE, &options).unwrap();
+        assert_eq!(extract(&image, &options).unwrap(), MESSAGE);
+    }
+
+    #[test]
+    fn test_capacity() {
+        let image = pixels(10, 10, 8, 2);
+        // 100 pixels * 3 channels * 2 bits = 600 bits = 75 bytes, minus the header
+        let options = LsbOptions {
+            bits_per_channel: 2,
+            ..LsbOptions::default()
+        };
+        assert_eq!(capacity(image.header(), &options).unwrap(), 67);
+
+        // 2147483647x2147483647 RGBA holds more sample bits than a usize can count
+        let max = 0x7fff_ffffu32.to_be_bytes();
+        let data = [max, max].concat().into_iter().chain([16, 6, 0, 0, 0]);
+        let huge = ImageHeader::try_from(data.collect::<Vec<_>>().as_slice()).unwrap();
+        let options = LsbOptions {
+            bits_per_channel: 4,
+            channels: Channels::from_str("rgba").unwrap(),
+        };
+        assert!(matches!(
+            capacity(&huge, &options),
+            Err(ProgramError::InvalidImageData(
+                ImageDataError::Samples { .. }
+            ))
+        ));
+    }
+
+    #[test]
+    fn test_header_does_not_fit() {
+        let key = ScatterKey::from([7; 32]);
+        let options = LsbOptions {
+            bits_per_channel: 4,
+            ..LsbOptions::default()
+        };
+        // 1x1 holds 3 bits, 2x2 holds 6 bytes: neither fits the 8-byte header
+        for (mut image, options) in [
+            (pixels(1, 1, 8, 2), LsbOptions::default()),
+            (pixels(2, 2, 8, 2), options),
+        ] {
+            assert!(matches!(
+                embed(&mut image, b"", &options),
+                Err(ProgramError::CapacityExceeded { available: 0, .. })
+            ));
+            assert!(matches!(
+                embed_scattered(&mut image, b"", &options, &key),
+                Err(ProgramError::CapacityExceeded { available: 0, .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn test_capacity_exceeded() {
+        let mut image = pixels(10, 10, 8, 2);
+        let payload = vec![0u8; 30];
+        assert!(matches!(
+            embed(&mut image, &payload, &LsbOptions::default()),
+            Err(ProgramError::CapacityExceeded {
+                needed: 30,
+                available: 29
+            })
+        ));
+    }
+
+    #[test]
+    fn test_extract_without_payload() {
+        let image = pixels(20, 20, 8, 2);
+        assert!(matches!(
+            extract(&image, &LsbOptions::default()),
+            Err(ProgramError::PayloadNotFound(PayloadLocation::Lsb))
+        ));
+    }
+
+    #[test]
+    fn test_invalid_options() {
+        let mut image = pixels(20, 20, 2, 0);
+        let options = LsbOptions {
+            bits_per_channel: 3,
+            ..LsbOptions::default()
+        };
+        assert!(embed(&mut image, MESSAGE, &options).is_err());
+
+        let mut image = pixels(20, 20, 8, 2);
+        let options = LsbOptions {
+            bits_per_channel: 1,
+            channels: Channels::from_str("a").unwrap(),
+        };
+        assert!(embed(&mut image, MESSAGE, &options).is_err());
+        assert!(Channels::from_str("rgbx").is_err());
+    }
+
+    #[test]
+    fn test_embed_extract_scattered() {
+        let key = ScatterKey::from([7; 32]);
+        let mut image = pixels(20, 20, 8, 6);
+        embed_scattered(&mut image, MESSAGE, &LsbOptions::default(), &key).unwrap();
+        assert_eq!(
+            extract_scattered(&image, &LsbOptions::default(), &key).unwrap(),
+            MESSAGE
+        );
+    }
+
+    #[test]
+    fn test_extract_scattered_with_wrong_key() {
+        let options = LsbOptions::default();
+        let mut image = pixels(20, 20, 8, 6);
+        embed_scattered(&mut image, MESSAGE, &options, &ScatterKey::from([7; 32])).unwrap();
+        assert!(matches!(
+            extract_scattered(&image, &options, &ScatterKey::from([8; 32])),
+            Err(ProgramError::PayloadNotFound(_))
+        ));
+        assert!(matches!(
+            extract(&image, &options),
+            Err(ProgramError::PayloadNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_scatter_key_from_passphrase() {
+        let params = KdfParams {
+            m_cost: 64,
+            t_cost: 1,
+            p_cost: 1,
+        };
+        let key = ScatterKey::from_passphrase_with_params(b"hunter2", params).unwrap();
+        let other = ScatterKey::from_passphrase_with_params(b"hunter3", params).unwrap();
+        assert_eq!(
+            key,
+            ScatterKey::from_passphrase_with_params(b"hunter2", params).unwrap()
+        );
+        assert_ne!(key, other);
+
+        let mut image = pixels(30, 30, 8, 2);
+        embed_scattered(&mut image, MESSAGE, &LsbOptions::default(), &key).unwrap();
+        assert!(extract_scattered(&image, &LsbOptions::default(), &other).is_err());
+        assert_eq!(
+            extract_scattered(&image, &LsbOptions::default(), &key).unwrap(),
+            MESSAGE
+        );
+    }
+
+    #[test]
+    fn test_scatter_visits_each_slot_once() {
+        let mut scatter = Scatter::new(&ScatterKey::from([1; 32]), 100);
+        let mut order: Vec<usize> = (0..100).map(|i| scatter.get(i)).collect();
+        assert_ne!(order, (0..100).collect::<Vec<_>>());
+        order.sort_unstable();
+        assert_eq!(order, (0..100).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_bit_groups_round_trip() {
+        for bits in 1..=4 {
+            let groups: Vec<u16> = bit_groups(MESSAGE, bits).collect();
+            assert!(groups.iter().all(|g| *g < 1 << bits));
+            assert_eq!(
+                from_bit_groups(groups.into_iter(), bits, MESSAGE.len()),
+                MESSAGE
+            );
+        }
+    }
+}
@@ -0,0 +1,68 @@
+//! Loads PNG files through a read-only memory map.
+//!
+//! Mapping a file lets `PngRef` parse it in place: chunk data is paged in by the
+//! operating system as it is read, and is never copied into a buffer of our own.
+
+use super::{Png, PngRef};
+use crate::ProgramResult;
+use memmap2::Mmap;
+use std::fs::File;
+use std::path::Path;
+
+/// A file mapped read-only into memory.
+///
+/// The mapping assumes that no other process truncates or rewrites the file while it is
+/// open; the commands of this crate replace files by renaming, which leaves existing
+/// mappings intact.
+#[derive(Debug)]
+pub struct MappedFile {
+    map: Mmap,
+}
+
+impl MappedFile {
+    /// Maps the file at `path` into memory.
+    pub fn open(path: impl AsRef<Path>) -> ProgramResult<Self> {
+        let file = File::open(path)?;
+        // SAFETY: the map is read-only and, as documented on `MappedFile`, the file is
+        // not expected to change while it is mapped.
+        let map = unsafe { Mmap::map(&file)? };
+        Ok(Self { map })
+    }
+
+    /// The contents of the file
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.map
+    }
+
+    /// Parses the mapped file into a `PngRef` whose chunks point into the map.
+    pub fn png_ref(&self) -> ProgramResult<PngRef<'_>> {
+        PngRef::try_from(self.as_bytes())
+    }
+
+    /// Parses the mapped file into an owned `Png`, copying the chunk data once.
+    pub fn to_png(&self) -> ProgramResult<Png> {
+        Ok(self.png_ref()?.to_png())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::DICE_PNG;
+    use std::fs;
+
+    #[test]
+    fn test_mapped_file() {
+        let path = std::env::temp_dir().join(format!("imagico-mapped-{}.png", std::process::id()));
+        fs::write(&path, DICE_PNG).unwrap();
+
+        let mapped = MappedFile::open(&path).unwrap();
+        assert_eq!(mapped.as_bytes(), DICE_PNG);
+        let png_ref = mapped.png_ref().unwrap();
+        assert_eq!(png_ref.chunks().len(), 7);
+        assert_eq!(mapped.to_png().unwrap().as_bytes(), DICE_PNG.to_vec());
+
+        drop(mapped);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,14 +1,171 @@
 //! Implements `Png` as described by the PNG specification.
 
+pub mod adam7;
 pub mod chunk;
 pub mod chunk_type;
+pub mod filter;
+pub mod header;
+pub mod lsb;
+pub mod mapped;
+pub mod palette;
+pub mod pixels;
+pub mod segment;
+pub mod stream;
+pub mod text;
+pub mod validate;
 
-use crate::{error::ProgramError, ProgramResult};
+use crate::{
+    crypto,
+    error::{ArgumentError, ImageDataError, ProgramError},
+    ProgramResult,
+};
 use core::str::FromStr;
-use std::io::{BufReader, Read};
+use std::ops::Range;
 
-use chunk::Chunk;
+use chunk::{Chunk, ChunkRef};
 use chunk_type::ChunkType;
+use header::ImageHeader;
+use lsb::LsbOptions;
+use pixels::Pixels;
+
+/// Returns the byte range of the first `chunk_type` chunk among the complete, valid
+/// chunks at the start of `trailing`, the chunks that `PngRef::payload` reads.
+fn trailing_chunk_range(trailing: &[u8], chunk_type: &ChunkType) -> Option<Range<usize>> {
+    let mut offset = 0;
+    while let Ok(chunk) = ChunkRef::try_from(&trailing[offset..]) {
+        let end = offset + 12 + chunk.length() as usize;
+        if chunk.chunk_type() == chunk_type {
+            return Some(offset..end);
+        }
+        offset = end;
+    }
+    None
+}
+
+/// Where a new `Chunk` is placed relative to the critical chunks of a `Png`.
+///
+/// Anything written after `IEND` is ignored (or rejected) by strict decoders,
+/// so every position other than `End` keeps the file spec-valid.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ChunkPosition {
+    /// Immediately before the `IEND` chunk
+    #[default]
+    BeforeIend,
+    /// Immediately after the `IHDR` chunk
+    AfterIhdr,
+    /// Immediately before the first `IDAT` chunk
+    BeforeIdat,
+    /// At the very end of the chunk list, after `IEND`.
+    /// Once written and read back, such chunks are part of `Png::trailing_data`.
+    End,
+}
+
+impl FromStr for ChunkPosition {
+    type Err = ProgramError;
+
+    fn from_str(s: &str) -> ProgramResult<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "before-iend" => Ok(Self::BeforeIend),
+            "after-ihdr" => Ok(Self::AfterIhdr),
+            "before-idat" => Ok(Self::BeforeIdat),
+            "end" => Ok(Self::End),
+            _ => Err(ProgramError::InvalidArgument(ArgumentError::ChunkPosition(
+                s.to_string(),
+            ))),
+        }
+    }
+}
+
+impl core::fmt::Display for ChunkPosition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let pos = match self {
+            Self::BeforeIend => "before-iend",
+            Self::AfterIhdr => "after-ihdr",
+            Self::BeforeIdat => "before-idat",
+            Self::End => "end",
+        };
+        write!(f, "{}", pos)
+    }
+}
+
+/// A way of hiding a payload in a `Png`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Method {
+    /// In the data of an ancillary chunk
+    Chunk,
+    /// In the low bits of pixel samples
+    Lsb(LsbOptions),
+    /// In the order of the palette entries of an indexed image
+    Palette,
+    /// In bytes appended after the `IEND` chunk
+    TrailingData,
+}
+
+/// How much a `Method` can hide in a particular `Png`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capacity {
+    /// Largest payload in bytes, or `None` if the method has no practical limit
+    pub payload: Option<usize>,
+    /// Bytes the method stores next to the payload, such as headers and checksums
+    pub framing: usize,
+}
+
+impl Capacity {
+    /// Largest message that still fits once encrypted by `crypto::encrypt`,
+    /// or `None` if the method has no practical limit
+    pub fn encrypted(&self) -> Option<usize> {
+        self.payload.map(|p| p.saturating_sub(crypto::OVERHEAD))
+    }
+}
+
+/// How a parser reacts to a broken rule of the PNG spec
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Strictness {
+    /// Fail with an error
+    #[default]
+    Strict,
+    /// Keep going and report the problem as a diagnostic
+    Warn,
+    /// Keep going silently
+    Ignore,
+}
+
+impl Strictness {
+    /// Fails with `error`, records it in `diagnostics` or drops it, as this level asks.
+    pub(crate) fn report(
+        self,
+        error: ProgramError,
+        diagnostics: &mut Vec<ProgramError>,
+    ) -> ProgramResult<()> {
+        match self {
+            Self::Strict => return Err(error),
+            Self::Warn => diagnostics.push(error),
+            Self::Ignore => {}
+        }
+        Ok(())
+    }
+}
+
+/// Options for `Png::parse`.
+///
+/// The default options reject the same files as `Png::try_from`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ParseOptions {
+    /// How to handle chunks whose stored CRC does not match their type and data
+    pub crc: Strictness,
+    /// How to handle a file that does not start with `Png::STANDARD_HEADER`
+    pub signature: Strictness,
+}
+
+impl ParseOptions {
+    /// Options that load damaged files and report every problem
+    pub fn lenient() -> Self {
+        Self {
+            crc: Strictness::Warn,
+            signature: Strictness::Warn,
+        }
+    }
+}
 
 /// A PNG container as described by the PNG spec
 /// http://www.libpng.org/pub/png/spec/1.2/PNG-Contents.html
@@ -16,28 +173,117 @@ use chunk_type::ChunkType;
 pub struct Png {
     header: [u8; 8],
     chunks: Vec<Chunk>,
+    trailing: Vec<u8>,
 }
 
 impl Png {
+    /// The default maximum size of the `IDAT` chunks written by `set_pixels`
+    pub const DEFAULT_IDAT_SIZE: usize = 8192;
+
     /// The first eight bytes of a PNG file,
     /// which always contain the following (decimal) values:
     pub const STANDARD_HEADER: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
 
+    /// Parses the chunks of a PNG file up to and including `IEND`, like `Png::try_from`.
+    ///
+    /// Problems that `options` lets through are returned as diagnostics next to the `Png`;
+    /// chunks with a wrong CRC keep it until `repair` is called. Errors that leave no way
+    /// to find the next chunk, such as truncation, always fail.
+    pub fn parse(bytes: &[u8], options: ParseOptions) -> ProgramResult<(Self, Vec<ProgramError>)> {
+        let (png, diagnostics) = PngRef::parse(bytes, options)?;
+        Ok((png.to_png(), diagnostics))
+    }
+
+    /// Fixes the problems a lenient `Png::parse` lets through: restores the standard
+    /// header and recomputes every wrong chunk CRC.
+    ///
+    /// Returns the number of fixes made.
+    pub fn repair(&mut self) -> usize {
+        let mut fixes = usize::from(self.header != Self::STANDARD_HEADER);
+        self.header = Self::STANDARD_HEADER;
+        for chunk in &mut self.chunks {
+            fixes += usize::from(chunk.repair_crc());
+        }
+        fixes
+    }
+
     /// Creates a `Png` from a list of chunks using the correct header
     pub fn from_chunks(chunks: Vec<Chunk>) -> Self {
         Self {
             header: Self::STANDARD_HEADER,
             chunks,
+            trailing: Vec::new(),
         }
     }
 
     /// Appends a chunk to the end of this `Png` file's `Chunk` list.
+    ///
+    /// Note that this places the chunk after `IEND` in a complete PNG file;
+    /// prefer `insert_chunk` to keep the file spec-valid.
     pub fn append_chunk(&mut self, chunk: Chunk) {
         self.chunks.push(chunk);
     }
 
+    /// Inserts a chunk into this `Png` file's `Chunk` list at the given `position`.
+    ///
+    /// This function returns an error if the chunk that `position` is relative to
+    /// cannot be found.
+    pub fn insert_chunk(&mut self, chunk: Chunk, position: ChunkPosition) -> ProgramResult<()> {
+        let index = self.insertion_index(position)?;
+        self.chunks.insert(index, chunk);
+        Ok(())
+    }
+
+    /// Splits `payload` into segments of at most `segment_size` bytes and inserts
+    /// one `chunk_type` chunk per segment, in order, at the given `position`.
+    ///
+    /// Returns the id shared by all segments of the payload.
+    pub fn insert_payload(
+        &mut self,
+        chunk_type: ChunkType,
+        payload: &[u8],
+        segment_size: usize,
+        position: ChunkPosition,
+    ) -> ProgramResult<u32> {
+        let (payload_id, chunks) = segment::to_chunks(&chunk_type, payload, segment_size)?;
+        let index = self.insertion_index(position)?;
+        let _ = self.chunks.splice(index..index, chunks);
+        Ok(payload_id)
+    }
+
+    /// Returns the payload stored in the `chunk_type` chunks of this `Png`.
+    ///
+    /// Segmented payloads are reassembled in order; otherwise the data of the first
+    /// matching chunk is returned as-is. Chunks at the start of the trailing data,
+    /// such as those placed at `ChunkPosition::End`, are searched as well.
+    pub fn payload(&self, chunk_type: &str) -> ProgramResult<Vec<u8>> {
+        self.as_png_ref().payload(chunk_type)
+    }
+
+    /// Returns a `PngRef` borrowing the chunks of this `Png`.
+    pub fn as_png_ref(&self) -> PngRef<'_> {
+        PngRef {
+            header: self.header,
+            chunks: self.chunks.iter().map(Chunk::as_chunk_ref).collect(),
+            trailing: &self.trailing,
+        }
+    }
+
+    /// Returns the index in the `Chunk` list that `position` refers to.
+    fn insertion_index(&self, position: ChunkPosition) -> ProgramResult<usize> {
+        match position {
+            ChunkPosition::BeforeIend => self.position_of("IEND"),
+            ChunkPosition::AfterIhdr => Ok(self.position_of("IHDR")? + 1),
+            ChunkPosition::BeforeIdat => self.position_of("IDAT"),
+            ChunkPosition::End => Ok(self.chunks.len()),
+        }
+    }
+
     /// Searches for a `Chunk` with the specified `chunk_type` and removes the first
     /// matching `Chunk` from this `Png` list of chunks.
+    ///
+    /// Chunks stored after `IEND`, which are read back as trailing data, are searched
+    /// last, as `Png::payload` does.
     pub fn remove_chunk(&mut self, chunk_type: &str) -> ProgramResult<Chunk> {
         let chunk_type = ChunkType::from_str(chunk_type)?;
         if let Some(pos) = self
@@ -46,18 +292,140 @@ impl Png {
             .position(|c| *c.chunk_type() == chunk_type)
         {
             Ok(self.chunks.remove(pos))
+        } else if let Some(range) = trailing_chunk_range(&self.trailing, &chunk_type) {
+            let chunk = Chunk::try_from(&self.trailing[range.clone()])?;
+            let _ = self.trailing.drain(range);
+            Ok(chunk)
         } else {
-            Err(ProgramError::ChunkOperationError(
-                "No matching Chunk found for chunk type".to_string(),
-            ))
+            Err(ProgramError::ChunkNotFound {
+                chunk_type: chunk_type.to_string(),
+                offset: None,
+            })
         }
     }
 
+    /// Returns the index of the first `Chunk` with the specified `chunk_type`.
+    fn position_of(&self, chunk_type: &str) -> ProgramResult<usize> {
+        let chunk_type = ChunkType::from_str(chunk_type)?;
+        self.chunks
+            .iter()
+            .position(|c| *c.chunk_type() == chunk_type)
+            .ok_or_else(|| ProgramError::ChunkNotFound {
+                chunk_type: chunk_type.to_string(),
+                offset: None,
+            })
+    }
+
     /// The header of this PNG.
     pub fn header(&self) -> &[u8; 8] {
         &self.header
     }
 
+    /// Parses the `IHDR` chunk of this `Png` into an `ImageHeader`.
+    ///
+    /// This function returns an error if there is no `IHDR` chunk or it violates the PNG spec.
+    pub fn header_info(&self) -> ProgramResult<ImageHeader> {
+        let ihdr = self
+            .chunk_by_type("IHDR")
+            .ok_or(ProgramError::ChunkNotFound {
+                chunk_type: "IHDR".to_string(),
+                offset: None,
+            })?;
+        ImageHeader::try_from(ihdr)
+    }
+
+    /// Concatenates the data of all `IDAT` chunks into a single zlib stream.
+    pub fn idat_data(&self) -> Vec<u8> {
+        self.chunks
+            .iter()
+            .filter(|c| c.chunk_type().bytes() == *b"IDAT")
+            .flat_map(|c| c.data().iter().copied())
+            .collect()
+    }
+
+    /// Decodes the image data of this `Png` into `Pixels`.
+    ///
+    /// The `PLTE` chunk, when present, is parsed so that indexed pixels can be looked up.
+    pub fn pixels(&self) -> ProgramResult<Pixels> {
+        let header = self.header_info()?;
+        let palette = self
+            .chunk_by_type("PLTE")
+            .map(|c| pixels::parse_palette(c.data()))
+            .transpose()?;
+        Pixels::decode(header, palette, &self.idat_data())
+    }
+
+    /// Encodes `pixels` and replaces the `IDAT` chunks of this `Png` with the result,
+    /// split into chunks of at most `idat_size` bytes.
+    ///
+    /// The new `IDAT` chunks take the place of the first original one. The interlace method
+    /// in the `IHDR` chunk is updated to match `pixels`; every other chunk is left untouched.
+    /// This function returns an error if `pixels` does not otherwise match the `IHDR` chunk
+    /// or this `Png` has no `IDAT` chunk.
+    pub fn set_pixels(&mut self, pixels: &Pixels, idat_size: usize) -> ProgramResult<()> {
+        let header = *pixels.header();
+        if header.with_interlace(false) != self.header_info()?.with_interlace(false) {
+            return Err(ProgramError::InvalidImageData(
+                ImageDataError::HeaderMismatch,
+            ));
+        }
+        if idat_size == 0 || idat_size > chunk::MAX_LEN as usize {
+            return Err(ProgramError::InvalidImageData(ImageDataError::IdatSize(
+                idat_size,
+            )));
+        }
+
+        let zlib = pixels.encode()?;
+        let index = self.position_of("IDAT")?;
+        self.chunks.retain(|c| c.chunk_type().bytes() != *b"IDAT");
+        let idat_type = ChunkType::from_str("IDAT")?;
+        let _ = self.chunks.splice(
+            index..index,
+            zlib.chunks(idat_size)
+                .map(|data| Chunk::new(idat_type.clone(), data.to_vec())),
+        );
+
+        let ihdr = self.position_of("IHDR")?;
+        if self.chunks[ihdr].data() != header.as_bytes() {
+            self.chunks[ihdr] = Chunk::new(ChunkType::from_str("IHDR")?, header.as_bytes());
+        }
+        Ok(())
+    }
+
+    /// Checks the chunk ordering and multiplicity rules of the PNG spec.
+    ///
+    /// Returns every rule this `Png` breaks, in file order, or an empty list for a
+    /// well-formed file. Any violation means the file fails validation.
+    pub fn validate(&self) -> Vec<validate::Violation> {
+        validate::violations(&self.as_png_ref())
+    }
+
+    /// Returns the largest payload that `method` can hide in this `Png`.
+    ///
+    /// The payload size excludes the framing each method adds; see `Capacity::encrypted`
+    /// for the size left for a message after encryption. This function returns
+    /// `ProgramError::Unsupported` if the method cannot be used with this image.
+    pub fn capacity(&self, method: Method) -> ProgramResult<Capacity> {
+        match method {
+            Method::Chunk => Ok(Capacity {
+                payload: Some(chunk::MAX_LEN as usize),
+                framing: 12,
+            }),
+            Method::Lsb(options) => Ok(Capacity {
+                payload: Some(lsb::capacity(&self.header_info()?, &options)?),
+                framing: lsb::HEADER_LEN,
+            }),
+            Method::Palette => Ok(Capacity {
+                payload: Some(palette::capacity(self)?),
+                framing: palette::HEADER_LEN,
+            }),
+            Method::TrailingData => Ok(Capacity {
+                payload: None,
+                framing: 0,
+            }),
+        }
+    }
+
     /// Lists the `Chunk`s stored in this `Png`
     pub fn chunks(&self) -> &[Chunk] {
         &self.chunks
@@ -80,49 +448,177 @@ impl Png {
             .iter()
             .flat_map(|x| x.as_bytes())
             .collect::<Vec<u8>>();
-        self.header.iter().chain(chunks.iter()).copied().collect()
+        self.header
+            .iter()
+            .chain(chunks.iter())
+            .chain(self.trailing.iter())
+            .copied()
+            .collect()
+    }
+
+    /// Returns the bytes stored after the `IEND` chunk.
+    ///
+    /// Decoders ignore these bytes, which makes them a simple but easily spotted place
+    /// to hide a payload. Chunks placed at `ChunkPosition::End` end up here once the
+    /// file is written and read back.
+    pub fn trailing_data(&self) -> &[u8] {
+        &self.trailing
+    }
+
+    /// Replaces the bytes stored after the `IEND` chunk with `data`.
+    pub fn set_trailing_data(&mut self, data: Vec<u8>) {
+        self.trailing = data;
+    }
+
+    /// Removes the bytes stored after the `IEND` chunk and returns them.
+    pub fn strip_trailing_data(&mut self) -> Vec<u8> {
+        core::mem::take(&mut self.trailing)
     }
 }
 
-impl TryFrom<&[u8]> for Png {
-    type Error = ProgramError;
+/// A PNG file that borrows its chunks from the bytes it was parsed from.
+///
+/// `PngRef` parses like `Png`, but its chunks are `ChunkRef`s whose data points into
+/// the input, so read-only work does not copy any chunk data. `to_png` makes an owned
+/// `Png` when the file needs to be edited.
+#[derive(Debug, Clone)]
+pub struct PngRef<'a> {
+    header: [u8; 8],
+    chunks: Vec<ChunkRef<'a>>,
+    trailing: &'a [u8],
+}
 
-    fn try_from(bytes: &[u8]) -> ProgramResult<Self> {
-        let mut reader = BufReader::new(bytes);
+impl<'a> PngRef<'a> {
+    /// Parses the chunks of a PNG file up to and including `IEND`, like `Png::parse`.
+    ///
+    /// Problems that `options` lets through are returned as diagnostics next to the `PngRef`.
+    pub fn parse(
+        bytes: &'a [u8],
+        options: ParseOptions,
+    ) -> ProgramResult<(Self, Vec<ProgramError>)> {
+        let mut diagnostics = Vec::new();
 
         // 1. Read the standard header
-        let mut header = [0u8; 8];
-        reader.read_exact(&mut header)?;
-        if header != Self::STANDARD_HEADER {
-            return Err(
-                ProgramError::TryFromError(format!("Received header doesn't match with STANDARD HEADER; received: {:?}, expected: {:?}",
-                header, Self::STANDARD_HEADER))
-            );
+        let header: [u8; 8] = bytes
+            .get(..8)
+            .and_then(|h| h.try_into().ok())
+            .ok_or(ProgramError::Truncated { offset: 0 })?;
+        if header != Png::STANDARD_HEADER {
+            let error = ProgramError::BadSignature { found: header };
+            options.signature.report(error, &mut diagnostics)?;
         }
 
-        // 2. Iterate through the list of chunks and process each chunk
+        // 2. Iterate through the list of chunks until `IEND` and process each chunk
         let mut chunks = vec![];
-        let mut data_len_buf = [0u8; 4];
-        // reads:
-        // ** `length` == 4 bytes
-        // ** `chunk_type` == 4 bytes
-        // ** `data` == `length` bytes
-        // ** `crc` == 4 bytes
-        while let Ok(()) = reader.read_exact(&mut data_len_buf) {
-            let chunk_position = 4 + u32::from_be_bytes(data_len_buf) + 4;
-            let mut chunk_buf = vec![0; chunk_position as usize];
-            reader.read_exact(&mut chunk_buf)?;
-            let chained_bytes = data_len_buf
-                .iter()
-                .copied()
-                .chain(chunk_buf.into_iter())
-                .collect::<Vec<u8>>();
+        let mut offset = header.len();
+        while offset < bytes.len() {
+            let chunk =
+                ChunkRef::parse_unchecked(&bytes[offset..]).map_err(|e| e.offset_by(offset))?;
+            if let Some(e) = chunk.crc_error() {
+                options.crc.report(e.offset_by(offset), &mut diagnostics)?;
+            }
+            // `length`, `chunk_type` and `crc` take 12 bytes next to the data
+            offset += 12 + chunk.length() as usize;
+            let is_iend = chunk.chunk_type().bytes() == *b"IEND";
+            chunks.push(chunk);
+            if is_iend {
+                break;
+            }
+        }
+
+        // 3. Keep whatever follows `IEND`
+        let png = Self {
+            header,
+            chunks,
+            trailing: &bytes[offset..],
+        };
+        Ok((png, diagnostics))
+    }
+
+    /// The header of this PNG.
+    pub fn header(&self) -> &[u8; 8] {
+        &self.header
+    }
+
+    /// Lists the `ChunkRef`s stored in this PNG, up to and including `IEND`.
+    pub fn chunks(&self) -> &[ChunkRef<'a>] {
+        &self.chunks
+    }
 
-            let chunk = Chunk::try_from(chained_bytes.as_slice())?;
+    /// Returns the first chunk of type `chunk_type`, if any.
+    pub fn chunk_by_type(&self, chunk_type: &str) -> Option<&ChunkRef<'a>> {
+        let chunk_type = ChunkType::from_str(chunk_type).ok()?;
+        self.chunks.iter().find(|c| *c.chunk_type() == chunk_type)
+    }
+
+    /// Parses the `IHDR` chunk into an `ImageHeader`, like `Png::header_info`.
+    pub fn header_info(&self) -> ProgramResult<ImageHeader> {
+        let ihdr = self
+            .chunk_by_type("IHDR")
+            .ok_or(ProgramError::ChunkNotFound {
+                chunk_type: "IHDR".to_string(),
+                offset: None,
+            })?;
+        ImageHeader::try_from(ihdr)
+    }
+
+    /// The bytes stored after the `IEND` chunk, borrowed from the input
+    pub fn trailing_data(&self) -> &'a [u8] {
+        self.trailing
+    }
+
+    /// Returns the payload stored in the `chunk_type` chunks, like `Png::payload`.
+    pub fn payload(&self, chunk_type: &str) -> ProgramResult<Vec<u8>> {
+        let chunk_type = ChunkType::from_str(chunk_type)?;
+        let chunks = self.chunks.iter().cloned();
+        segment::payload(&chunk_type, chunks.chain(self.trailing_chunks()))
+    }
+
+    /// Parses the complete, valid chunks at the start of the trailing data.
+    fn trailing_chunks(&self) -> Vec<ChunkRef<'a>> {
+        let mut chunks = Vec::new();
+        let mut offset = 0;
+        while let Ok(chunk) = ChunkRef::try_from(&self.trailing[offset..]) {
+            offset += 12 + chunk.length() as usize;
             chunks.push(chunk);
         }
+        chunks
+    }
 
-        Ok(Self::from_chunks(chunks))
+    /// Checks the chunk ordering and multiplicity rules of the PNG spec, like `Png::validate`.
+    pub fn validate(&self) -> Vec<validate::Violation> {
+        validate::violations(self)
+    }
+
+    /// Copies this PNG into an owned `Png`, keeping every byte as-is.
+    pub fn to_png(&self) -> Png {
+        Png {
+            header: self.header,
+            chunks: self.chunks.iter().map(ChunkRef::to_chunk).collect(),
+            trailing: self.trailing.to_vec(),
+        }
+    }
+}
+
+impl<'a> TryFrom<&'a [u8]> for PngRef<'a> {
+    type Error = ProgramError;
+
+    /// Parses the chunks of a PNG file without copying their data.
+    fn try_from(bytes: &'a [u8]) -> ProgramResult<Self> {
+        let (png, _) = Self::parse(bytes, ParseOptions::default())?;
+        Ok(png)
+    }
+}
+
+impl TryFrom<&[u8]> for Png {
+    type Error = ProgramError;
+
+    /// Parses the chunks of a PNG file up to and including `IEND`.
+    ///
+    /// Any bytes after `IEND` are kept as trailing data, whether or not they form chunks.
+    fn try_from(bytes: &[u8]) -> ProgramResult<Self> {
+        let (png, _) = Self::parse(bytes, ParseOptions::default())?;
+        Ok(png)
     }
 }
 
@@ -132,6 +628,9 @@ impl core::fmt::Display for Png {
         for chunk in self.chunks() {
             write!(f, "{}", chunk)?;
         }
+        if !self.trailing.is_empty() {
+            writeln!(f, "Trailing data: {} bytes", self.trailing.len())?;
+        }
         Ok(())
     }
 }
@@ -149,13 +648,11 @@ mod tests {
     }
 
     fn testing_chunks() -> Vec<Chunk> {
-        let mut chunks = Vec::new();
-
-        chunks.push(chunk_from_strings("FrSt", "I am the first chunk").unwrap());
-        chunks.push(chunk_from_strings("miDl", "I am another chunk").unwrap());
-        chunks.push(chunk_from_strings("LASt", "I am the last chunk").unwrap());
-
-        chunks
+        vec![
+            chunk_from_strings("FrSt", "I am the first chunk").unwrap(),
+            chunk_from_strings("miDl", "I am another chunk").unwrap(),
+            chunk_from_strings("LASt", "I am the last chunk").unwrap(),
+        ]
     }
 
     fn chunk_from_strings(chunk_type: &str, data: &str) -> ProgramResult<Chunk> {
@@ -206,7 +703,64 @@ mod tests {
 
         let png = Png::try_from(bytes.as_ref());
 
-        assert!(png.is_err());
+        assert!(matches!(png, Err(ProgramError::BadSignature { .. })));
+    }
+
+    #[test]
+    fn test_crc_mismatch_offset() {
+        let mut bytes = DICE_PNG.to_vec();
+        // Corrupt the last byte of the IHDR CRC: 8 (signature) + 8 (length, type) + 13 (data)
+        bytes[32] ^= 0xff;
+
+        let png = Png::try_from(bytes.as_ref());
+
+        match png {
+            Err(err @ ProgramError::CrcMismatch { .. }) => assert_eq!(err.offset(), Some(29)),
+            _ => panic!("expected a CRC mismatch"),
+        }
+    }
+
+    #[test]
+    fn test_lenient_parse() {
+        let mut bytes = DICE_PNG.to_vec();
+        bytes[0] = 0;
+        bytes[32] ^= 0xff;
+
+        assert!(Png::parse(&bytes, ParseOptions::default()).is_err());
+
+        let (png, diagnostics) = Png::parse(&bytes, ParseOptions::lenient()).unwrap();
+        assert_eq!(diagnostics.len(), 2);
+        assert!(matches!(diagnostics[0], ProgramError::BadSignature { .. }));
+        assert_eq!(diagnostics[1].offset(), Some(29));
+        assert_eq!(png.as_bytes(), bytes);
+
+        let options = ParseOptions {
+            crc: Strictness::Ignore,
+            signature: Strictness::Warn,
+        };
+        let (_, diagnostics) = Png::parse(&bytes, options).unwrap();
+        assert_eq!(diagnostics.len(), 1);
+    }
+
+    #[test]
+    fn test_repair() {
+        let mut bytes = DICE_PNG.to_vec();
+        bytes[0] = 0;
+        bytes[32] ^= 0xff;
+
+        let (mut png, _) = Png::parse(&bytes, ParseOptions::lenient()).unwrap();
+        assert_eq!(png.repair(), 2);
+        assert_eq!(png.as_bytes(), DICE_PNG.to_vec());
+        assert_eq!(png.repair(), 0);
+    }
+
+    #[test]
+    fn test_truncated_png() {
+        let bytes = &DICE_PNG[..DICE_PNG.len() - 6];
+
+        let png = Png::try_from(bytes);
+
+        assert!(matches!(png, Err(ProgramError::Truncated { .. })));
     }
 
     #[test]
@@ -255,6 +809,128 @@ mod tests {
         assert_eq!(&chunk.data_as_string().unwrap(), "Message");
     }
 
+    #[test]
+    fn test_insert_chunk_before_iend() {
+        let mut png = Png::try_from(&DICE_PNG[..]).unwrap();
+        png.insert_chunk(
+            chunk_from_strings("TeSt", "Message").unwrap(),
+            ChunkPosition::BeforeIend,
+        )
+        .unwrap();
+        let chunks = png.chunks();
+        assert_eq!(&chunks[chunks.len() - 1].chunk_type().to_string(), "IEND");
+        assert_eq!(&chunks[chunks.len() - 2].chunk_type().to_string(), "TeSt");
+    }
+
+    #[test]
+    fn test_insert_chunk_after_ihdr() {
+        let mut png = Png::try_from(&DICE_PNG[..]).unwrap();
+        png.insert_chunk(
+            chunk_from_strings("TeSt", "Message").unwrap(),
+            ChunkPosition::AfterIhdr,
+        )
+        .unwrap();
+        let chunks = png.chunks();
+        assert_eq!(&chunks[0].chunk_type().to_string(), "IHDR");
+        assert_eq!(&chunks[1].chunk_type().to_string(), "TeSt");
+    }
+
+    #[test]
+    fn test_insert_chunk_before_idat() {
+        let mut png = Png::try_from(&DICE_PNG[..]).unwrap();
+        png.insert_chunk(
+            chunk_from_strings("TeSt", "Message").unwrap(),
+            ChunkPosition::BeforeIdat,
+        )
+        .unwrap();
+        let chunks = png.chunks();
+        let pos = chunks
+            .iter()
+            .position(|c| c.chunk_type().to_string() == "TeSt")
+            .unwrap();
+        assert_eq!(&chunks[pos + 1].chunk_type().to_string(), "IDAT");
+    }
+
+    #[test]
+    fn test_insert_chunk_missing_anchor() {
+        let mut png = testing_png();
+        let res = png.insert_chunk(
+            chunk_from_strings("TeSt", "Message").unwrap(),
+            ChunkPosition::BeforeIend,
+        );
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn test_segmented_payload_round_trip() {
+        let mut png = Png::try_from(&DICE_PNG[..]).unwrap();
+        let payload: Vec<u8> = (0..=255).cycle().take(1000).collect();
+        let _ = png
+            .insert_payload(
+                ChunkType::from_str("TeSt").unwrap(),
+                &payload,
+                64,
+                ChunkPosition::AfterIhdr,
+            )
+            .unwrap();
+
+        let png = Png::try_from(png.as_bytes().as_slice()).unwrap();
+        let chunks = png.chunks();
+        assert_eq!(&chunks[chunks.len() - 1].chunk_type().to_string(), "IEND");
+        assert_eq!(png.payload("TeSt").unwrap(), payload);
+    }
+
+    #[test]
+    fn test_segmented_payload_missing_segment() {
+        let mut png = Png::try_from(&DICE_PNG[..]).unwrap();
+        let _ = png
+            .insert_payload(
+                ChunkType::from_str("TeSt").unwrap(),
+                b"I am split across several chunks",
+                8,
+                ChunkPosition::BeforeIend,
+            )
+            .unwrap();
+        let _ = png.remove_chunk("TeSt").unwrap();
+
+        assert!(matches!(
+            png.payload("TeSt"),
+            Err(ProgramError::MissingSegment { index: 0, .. })
+        ));
+    }
+
+    #[test]
+    fn test_plain_payload() {
+        let png = testing_png();
+        assert_eq!(png.payload("miDl").unwrap(), b"I am another chunk");
+        assert!(matches!(
+            png.payload("TeSt"),
+            Err(ProgramError::ChunkNotFound { .. })
+        ));
+    }
+
+    #[test]
+    fn test_chunk_position_from_str() {
+        assert_eq!(
+            ChunkPosition::from_str("before-iend").unwrap(),
+            ChunkPosition::BeforeIend
+        );
+        assert_eq!(
+            ChunkPosition::from_str("after-ihdr").unwrap(),
+            ChunkPosition::AfterIhdr
+        );
+        assert!(ChunkPosition::from_str("middle").is_err());
+    }
+
+    #[test]
+    fn test_unknown_chunk_position() {
+        assert!(matches!(
+            ChunkPosition::from_str("middle"),
+            Err(ProgramError::InvalidArgument(ArgumentError::ChunkPosition(position)))
+                if position == "middle"
+        ));
+    }
+
     #[test]
     fn test_remove_chunk() {
         let mut png = testing_png();
@@ -262,6 +938,10 @@ mod tests {
         let _ = png.remove_chunk("TeSt").unwrap();
         let chunk = png.chunk_by_type("TeSt");
         assert!(chunk.is_none());
+        assert!(matches!(
+            png.remove_chunk("TeSt"),
+            Err(ProgramError::ChunkNotFound { .. })
+        ));
     }
 
     #[test]
@@ -270,11 +950,173 @@ mod tests {
         assert!(png.is_ok());
     }
 
+    #[test]
+    fn test_header_info() {
+        let png = Png::try_from(&DICE_PNG[..]).unwrap();
+        let header = png.header_info().unwrap();
+        assert_eq!(header.width(), 50);
+        assert_eq!(header.height(), 50);
+        assert_eq!(header.bit_depth(), 8);
+        assert_eq!(header.color_type(), header::ColorType::Rgba);
+        assert!(!header.is_interlaced());
+
+        assert!(testing_png().header_info().is_err());
+    }
+
+    #[test]
+    fn test_pixels() {
+        let png = Png::try_from(&DICE_PNG[..]).unwrap();
+        let pixels = png.pixels().unwrap();
+        assert_eq!(pixels.data().len(), 50 * 50 * 4);
+        assert_eq!(pixels.pixel(0, 0), [0, 0, 0, 0]);
+        assert_eq!(pixels.pixel(25, 25), [240, 240, 240, 255]);
+    }
+
+    #[test]
+    fn test_set_pixels_round_trip() {
+        let mut png = Png::try_from(&DICE_PNG[..]).unwrap();
+        let mut pixels = png.pixels().unwrap();
+        pixels.set_sample(25, 25, 0, 17);
+        png.set_pixels(&pixels, 1000).unwrap();
+
+        let png = Png::try_from(png.as_bytes().as_slice()).unwrap();
+        let original = Png::try_from(&DICE_PNG[..]).unwrap();
+        let without_idat = |png: &Png| -> Vec<Chunk> {
+            png.chunks()
+                .iter()
+                .filter(|c| c.chunk_type().to_string() != "IDAT")
+                .cloned()
+                .collect()
+        };
+        assert_eq!(without_idat(&png), without_idat(&original));
+        assert!(png.chunks().len() > original.chunks().len());
+        assert!(png
+            .chunks()
+            .iter()
+            .all(|c| c.chunk_type().to_string() != "IDAT" || c.length() <= 1000));
+        assert_eq!(png.pixels().unwrap(), pixels);
+        assert_eq!(png.pixels().unwrap().pixel(25, 25), [17, 240, 240, 255]);
+    }
+
+    #[test]
+    fn test_set_interlaced_pixels() {
+        let mut png = Png::try_from(&DICE_PNG[..]).unwrap();
+        let mut pixels = png.pixels().unwrap();
+        pixels.set_interlaced(true);
+        png.set_pixels(&pixels, Png::DEFAULT_IDAT_SIZE).unwrap();
+
+        let png = Png::try_from(png.as_bytes().as_slice()).unwrap();
+        assert!(png.header_info().unwrap().is_interlaced());
+        assert_eq!(png.pixels().unwrap(), pixels);
+
+        let mut pixels = png.pixels().unwrap();
+        pixels.set_interlaced(false);
+        let mut png = png;
+        png.set_pixels(&pixels, Png::DEFAULT_IDAT_SIZE).unwrap();
+        assert!(!png.header_info().unwrap().is_interlaced());
+        assert_eq!(
+            png.pixels().unwrap().data(),
+            Png::try_from(&DICE_PNG[..])
+                .unwrap()
+                .pixels()
+                .unwrap()
+                .data()
+        );
+    }
+
+    #[test]
+    fn test_capacity() {
+        let png = Png::try_from(DICE_PNG.as_slice()).unwrap();
+        // 50x50 pixels * 3 channels * 1 bit = 937 bytes, minus the LSB header
+        let lsb = png.capacity(Method::Lsb(LsbOptions::default())).unwrap();
+        assert_eq!(lsb.payload, Some(929));
+        assert_eq!(lsb.encrypted(), Some(929 - crypto::OVERHEAD));
+
+        let chunk = png.capacity(Method::Chunk).unwrap();
+        assert_eq!(chunk.payload, Some(chunk::MAX_LEN as usize));
+        assert_eq!(
+            png.capacity(Method::TrailingData).unwrap().encrypted(),
+            None
+        );
+        assert!(matches!(
+            png.capacity(Method::Palette),
+            Err(ProgramError::Unsupported(_))
+        ));
+    }
+
+    #[test]
+    fn test_trailing_data() {
+        let bytes: Vec<u8> = DICE_PNG.iter().chain(b"stray").copied().collect();
+        let mut png = Png::try_from(bytes.as_slice()).unwrap();
+        assert_eq!(png.trailing_data(), b"stray");
+        assert_eq!(
+            png.chunks().last().unwrap().chunk_type().to_string(),
+            "IEND"
+        );
+        assert_eq!(png.as_bytes(), bytes);
+
+        assert_eq!(png.strip_trailing_data(), b"stray");
+        assert_eq!(png.as_bytes(), DICE_PNG.to_vec());
+    }
+
+    #[test]
+    fn test_partial_chunk_after_iend() {
+        // Looks like the start of a chunk, but ends early
+        let tail = [0, 0, 0, 9, b'a', b'b'];
+        let bytes: Vec<u8> = DICE_PNG.iter().chain(tail.iter()).copied().collect();
+        let png = Png::try_from(bytes.as_slice()).unwrap();
+        assert_eq!(png.trailing_data(), tail);
+    }
+
+    #[test]
+    fn test_set_trailing_data() {
+        let mut png = Png::try_from(&DICE_PNG[..]).unwrap();
+        png.set_trailing_data(b"hidden".to_vec());
+        let png = Png::try_from(png.as_bytes().as_slice()).unwrap();
+        assert_eq!(png.trailing_data(), b"hidden");
+    }
+
+    #[test]
+    fn test_chunk_at_end_is_trailing_data() {
+        let mut png = Png::try_from(&DICE_PNG[..]).unwrap();
+        let chunk = chunk_from_strings("TeSt", "Message").unwrap();
+        png.insert_chunk(chunk.clone(), ChunkPosition::End).unwrap();
+
+        let png = Png::try_from(png.as_bytes().as_slice()).unwrap();
+        assert!(png.chunk_by_type("TeSt").is_none());
+        assert_eq!(png.trailing_data(), chunk.as_bytes());
+        assert_eq!(png.payload("TeSt").unwrap(), b"Message");
+
+        let mut png = png;
+        assert_eq!(png.remove_chunk("TeSt").unwrap(), chunk);
+        assert!(png.trailing_data().is_empty());
+        assert!(matches!(
+            png.payload("TeSt"),
+            Err(ProgramError::ChunkNotFound { .. })
+        ));
+    }
+
+    #[test]
+    fn test_png_ref() {
+        let bytes: Vec<u8> = DICE_PNG.iter().chain(b"stray").copied().collect();
+        let png_ref = PngRef::try_from(bytes.as_slice()).unwrap();
+        let png = Png::try_from(bytes.as_slice()).unwrap();
+        assert_eq!(png_ref.chunks().len(), png.chunks().len());
+        assert_eq!(png_ref.header_info().unwrap(), png.header_info().unwrap());
+        assert_eq!(png_ref.trailing_data(), b"stray");
+
+        // Chunk data points into the input instead of a copy
+        let data = png_ref.chunk_by_type("IDAT").unwrap().data();
+        assert!(bytes.as_ptr_range().contains(&data.as_ptr()));
+
+        assert_eq!(png_ref.to_png().as_bytes(), bytes);
+    }
+
     #[test]
     fn test_as_bytes() {
         let png = Png::try_from(&DICE_PNG[..]).unwrap();
         let actual = png.as_bytes();
-        let expected: Vec<u8> = DICE_PNG.iter().copied().collect();
+        let expected = DICE_PNG.to_vec();
         assert_eq!(actual, expected);
     }
 
@@ -0,0 +1,358 @@
+//! Implements embedding of payloads into the order of the palette entries of indexed images.
+//!
+//! The entries of a `PLTE` chunk can be stored in any order as long as the pixel indices
+//! are remapped to match, so every ordering renders exactly the same image. A palette of
+//! `n` distinct entries has `n!` orderings; the payload is read as a number and written as
+//! one of them, counting from the entries sorted by color and alpha.
+
+use super::{chunk::Chunk, chunk_type::ChunkType, header::ColorType, pixels, Png};
+use crate::{
+    error::{PayloadLocation, ProgramError, UnsupportedFeature},
+    ProgramResult,
+};
+use core::str::FromStr;
+
+/// Marks the start of a palette payload
+pub const MAGIC: [u8; 2] = *b"iP";
+
+/// Length of the header written before the payload: magic bytes and a 2-byte length
+pub const HEADER_LEN: usize = 4;
+
+/// Returns the largest payload, in bytes, that `embed` can fit into the palette of `png`.
+pub fn capacity(png: &Png) -> ProgramResult<usize> {
+    let entries = entries(png)?;
+    Ok(framed_capacity(entries.len()).saturating_sub(HEADER_LEN))
+}
+
+/// Reorders the palette of `png` so that its order encodes `payload`, and remaps the pixel
+/// indices and the `tRNS`, `hIST` and `bKGD` chunks to match.
+///
+/// The rendered image is unchanged. This function returns `ProgramError::Unsupported`
+/// if the image is not indexed or its palette holds duplicate entries, and
+/// `ProgramError::CapacityExceeded` if the payload does not fit.
+pub fn embed(png: &mut Png, payload: &[u8]) -> ProgramResult<()> {
+    let entries = entries(png)?;
+    if has_duplicates(&entries) {
+        return Err(ProgramError::Unsupported(
+            UnsupportedFeature::PaletteDuplicates,
+        ));
+    }
+    let available = framed_capacity(entries.len()).saturating_sub(HEADER_LEN);
+    if payload.len() > available {
+        return Err(ProgramError::CapacityExceeded {
+            needed: payload.len(),
+            available,
+        });
+    }
+
+    // The framed payload, zero-padded to the capacity, as a big-endian number below `n!`
+    let mut number: Vec<u8> = MAGIC
+        .iter()
+        .chain((payload.len() as u16).to_be_bytes().iter())
+        .chain(payload.iter())
+        .copied()
+        .collect();
+    number.resize(framed_capacity(entries.len()), 0);
+
+    // Mixed-radix digits: digit `i` picks one of the `n - i` entries not yet placed
+    let n = entries.len();
+    let mut digits = vec![0usize; n];
+    for i in (0..n).rev() {
+        digits[i] = div_rem(&mut number, (n - i) as u32) as usize;
+    }
+
+    let mut remaining = sorted_indices(&entries);
+    let order: Vec<usize> = digits.iter().map(|d| remaining.remove(*d)).collect();
+    reorder(png, &entries, &order)
+}
+
+/// Reads a payload written by `embed` from the palette order of `png`.
+///
+/// This function returns `ProgramError::PayloadNotFound` if the palette order holds no payload.
+pub fn extract(png: &Png) -> ProgramResult<Vec<u8>> {
+    let entries = entries(png)?;
+    let not_found = || ProgramError::PayloadNotFound(PayloadLocation::Palette);
+    let n = entries.len();
+    let capacity = framed_capacity(n);
+    if has_duplicates(&entries) || capacity < HEADER_LEN {
+        return Err(not_found());
+    }
+
+    let mut remaining: Vec<Entry> = sorted_indices(&entries)
+        .into_iter()
+        .map(|i| entries[i])
+        .collect();
+    // One spare byte, since `n!` may exceed 256 to the power of the capacity
+    let mut number = vec![0u8; capacity + 1];
+    for (i, entry) in entries.iter().enumerate() {
+        let digit = remaining
+            .iter()
+            .position(|e| e == entry)
+            .ok_or_else(not_found)?;
+        let _ = remaining.remove(digit);
+        mul_add(&mut number, (n - i) as u32, digit as u32);
+    }
+
+    if number[0] != 0 || number[1..3] != MAGIC {
+        return Err(not_found());
+    }
+    let len = u16::from_be_bytes([number[3], number[4]]) as usize;
+    if len > capacity - HEADER_LEN {
+        return Err(not_found());
+    }
+    Ok(number[1 + HEADER_LEN..1 + HEADER_LEN + len].to_vec())
+}
+
+/// A palette color and its alpha value from the `tRNS` chunk
+type Entry = ([u8; 3], u8);
+
+/// Returns the palette entries of `png`, which must be an indexed image.
+fn entries(png: &Png) -> ProgramResult<Vec<Entry>> {
+    if png.header_info()?.color_type() != ColorType::Indexed {
+        return Err(ProgramError::Unsupported(
+            UnsupportedFeature::PaletteNotIndexed,
+        ));
+    }
+    let palette = match png.chunk_by_type("PLTE") {
+        Some(chunk) => pixels::parse_palette(chunk.data())?,
+        None => {
+            return Err(ProgramError::ChunkNotFound {
+                chunk_type: "PLTE".to_string(),
+                offset: None,
+            })
+        }
+    };
+    let alpha = png.chunk_by_type("tRNS").map(|c| c.data()).unwrap_or(&[]);
+    Ok(palette
+        .into_iter()
+        .enumerate()
+        .map(|(i, color)| (color, alpha.get(i).copied().unwrap_or(255)))
+        .collect())
+}
+
+fn has_duplicates(entries: &[Entry]) -> bool {
+    let mut sorted = entries.to_vec();
+    sorted.sort_unstable();
+    sorted.windows(2).any(|w| w[0] == w[1])
+}
+
+/// The indices of `entries` in ascending order of color and alpha
+fn sorted_indices(entries: &[Entry]) -> Vec<usize> {
+    let mut indices: Vec<usize> = (0..entries.len()).collect();
+    indices.sort_by_key(|i| entries[*i]);
+    indices
+}
+
+/// Number of bytes that fit in the orderings of `n` entries: the largest `k` with `256^k <= n!`
+fn framed_capacity(n: usize) -> usize {
+    let mut factorial = vec![0u8; n + 1];
+    factorial[n] = 1;
+    for k in 2..=n {
+        mul_add(&mut factorial, k as u32, 0);
+    }
+    let leading = factorial.iter().position(|b| *b != 0).unwrap_or(n);
+    let bits = (factorial.len() - leading) * 8 - factorial[leading].leading_zeros() as usize;
+    (bits - 1) / 8
+}
+
+/// Replaces the palette of `png` with `entries` in the given `order`, where `order[i]` is
+/// the old index of the new entry `i`, and remaps everything that refers to palette indices.
+fn reorder(png: &mut Png, entries: &[Entry], order: &[usize]) -> ProgramResult<()> {
+    let mut remap: Vec<u16> = (0..=255).collect();
+    for (new, old) in order.iter().enumerate() {
+        remap[*old] = new as u16;
+    }
+
+    let mut pixels = png.pixels()?;
+    for y in 0..pixels.height() {
+        for x in 0..pixels.width() {
+            let index = pixels.sample(x, y, 0);
+            pixels.set_sample(x, y, 0, remap[index as usize]);
+        }
+    }
+    png.set_pixels(&pixels, Png::DEFAULT_IDAT_SIZE)?;
+
+    let plte: Vec<u8> = order.iter().flat_map(|i| entries[*i].0).collect();
+    replace_data(png, "PLTE", plte)?;
+    if png.chunk_by_type("tRNS").is_some() {
+        replace_data(png, "tRNS", order.iter().map(|i| entries[*i].1).collect())?;
+    }
+    if let Some(hist) = png.chunk_by_type("hIST") {
+        let hist = hist.data();
+        if hist.len() == entries.len() * 2 {
+            let hist = order
+                .iter()
+                .flat_map(|i| [hist[i * 2], hist[i * 2 + 1]])
+                .collect();
+            replace_data(png, "hIST", hist)?;
+        }
+    }
+    if let Some(bkgd) = png.chunk_by_type("bKGD") {
+        if let [index] = *bkgd.data() {
+            replace_data(png, "bKGD", vec![remap[index as usize] as u8])?;
+        }
+    }
+    Ok(())
+}
+
+/// Replaces the data of the first chunk of `chunk_type` in `png`.
+fn replace_data(png: &mut Png, chunk_type: &str, data: Vec<u8>) -> ProgramResult<()> {
+    let index = png.position_of(chunk_type)?;
+    png.chunks[index] = Chunk::new(ChunkType::from_str(chunk_type)?, data);
+    Ok(())
+}
+
+/// Divides the big-endian number `number` by `divisor` in place and returns the remainder.
+fn div_rem(number: &mut [u8], divisor: u32) -> u32 {
+    let mut remainder = 0u32;
+    for byte in number.iter_mut() {
+        let value = (remainder << 8) | *byte as u32;
+        *byte = (value / divisor) as u8;
+        remainder = value % divisor;
+    }
+    remainder
+}
+
+/// Sets the big-endian number `number` to `number * factor + add`, dropping any overflow.
+fn mul_add(number: &mut [u8], factor: u32, add: u32) {
+    let mut carry = add;
+    for byte in number.iter_mut().rev() {
+        let value = *byte as u32 * factor + carry;
+        *byte = value as u8;
+        carry = value >> 8;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::png::{header::ImageHeader, pixels::Pixels};
+    use crate::utils::DICE_PNG;
+    use std::convert::TryFrom;
+
+    const MESSAGE: &[u8] = b"Hidden in plain sight";
+
+    fn chunk(chunk_type: &str, data: Vec<u8>) -> Chunk {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data)
+    }
+
+    /// A 16x16 indexed image with a sorted palette of `colors` entries, half of them translucent
+    fn indexed_png(colors: usize) -> Png {
+        let header: Vec<u8> = [0, 0, 0, 16, 0, 0, 0, 16, 8, 3, 0, 0, 0].to_vec();
+        let pixels = Pixels::from_raw(
+            ImageHeader::try_from(header.as_slice()).unwrap(),
+            None,
+            (0..256).map(|i| (i * 7 % colors) as u8).collect(),
+        )
+        .unwrap();
+        let plte = (0..colors)
+            .flat_map(|i| [i as u8, 0, 255 - i as u8])
+            .collect();
+        let trns = (0..colors / 2).map(|i| (i * 4) as u8).collect();
+        Png::from_chunks(vec![
+            chunk("IHDR", header),
+            chunk("PLTE", plte),
+            chunk("tRNS", trns),
+            chunk("bKGD", vec![3]),
+            chunk("IDAT", pixels.encode().unwrap()),
+            chunk("IEND", Vec::new()),
+        ])
+    }
+
+    /// The color and alpha of every pixel, as a viewer would render them
+    fn rendered(png: &Png) -> Vec<Entry> {
+        let entries = entries(png).unwrap();
+        let pixels = png.pixels().unwrap();
+        (0..16)
+            .flat_map(|y| (0..16).map(move |x| (x, y)))
+            .map(|(x, y)| entries[pixels.sample(x, y, 0) as usize])
+            .collect()
+    }
+
+    #[test]
+    fn test_embed_extract() {
+        let mut png = indexed_png(64);
+        let original = rendered(&png);
+        embed(&mut png, MESSAGE).unwrap();
+        assert_eq!(rendered(&png), original);
+
+        let png = Png::try_from(png.as_bytes().as_slice()).unwrap();
+        assert_eq!(extract(&png).unwrap(), MESSAGE);
+        assert_eq!(rendered(&png), original);
+    }
+
+    #[test]
+    fn test_background_is_remapped() {
+        let mut png = indexed_png(64);
+        let background = entries(&png).unwrap()[3];
+        embed(&mut png, MESSAGE).unwrap();
+        let index = png.chunk_by_type("bKGD").unwrap().data()[0];
+        assert_eq!(entries(&png).unwrap()[index as usize], background);
+    }
+
+    #[test]
+    fn test_capacity() {
+        // 64! is just below 2^296, so 36 bytes fit
+        assert_eq!(framed_capacity(64), 36);
+        assert_eq!(framed_capacity(256), 210);
+        assert_eq!(capacity(&indexed_png(64)).unwrap(), 32);
+        assert_eq!(capacity(&indexed_png(4)).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_capacity_exceeded() {
+        let mut png = indexed_png(16);
+        assert!(matches!(
+            embed(&mut png, MESSAGE),
+            Err(ProgramError::CapacityExceeded {
+                needed: 21,
+                available: 1
+            })
+        ));
+    }
+
+    #[test]
+    fn test_extract_without_payload() {
+        assert!(matches!(
+            extract(&indexed_png(64)),
+            Err(ProgramError::PayloadNotFound(PayloadLocation::Palette))
+        ));
+    }
+
+    #[test]
+    fn test_duplicate_entries() {
+        let mut png = indexed_png(64);
+        replace_data(&mut png, "PLTE", vec![0; 64 * 3]).unwrap();
+        replace_data(&mut png, "tRNS", Vec::new()).unwrap();
+        assert!(matches!(
+            embed(&mut png, MESSAGE),
+            Err(ProgramError::Unsupported(
+                UnsupportedFeature::PaletteDuplicates
+            ))
+        ));
+    }
+
+    #[test]
+    fn test_not_indexed() {
+        let mut png = Png::try_from(DICE_PNG.as_slice()).unwrap();
+        assert!(matches!(
+            embed(&mut png, MESSAGE),
+            Err(ProgramError::Unsupported(
+                UnsupportedFeature::PaletteNotIndexed
+            ))
+        ));
+    }
+
+    #[test]
+    fn test_big_number_arithmetic() {
+        let mut number = vec![0u8; 4];
+        mul_add(&mut number, 1000, 234);
+        mul_add(&mut number, 1000, 567);
+        assert_eq!(
+            u32::from_be_bytes([number[0], number[1], number[2], number[3]]),
+            234_567
+        );
+        assert_eq!(div_rem(&mut number, 1000), 567);
+        assert_eq!(number, 234u32.to_be_bytes());
+    }
+}
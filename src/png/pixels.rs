@@ -0,0 +1,541 @@
+//! Implements `Pixels`, the decoded image data of a PNG file.
+
+use super::{
+    adam7::{Pass, PASSES},
+    filter::{self, FilterType},
+    header::{ColorType, ImageHeader},
+};
+use crate::{
+    error::{ImageDataError, ProgramError},
+    ProgramResult,
+};
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+use std::io::{Read, Write};
+
+/// The unfiltered samples of an image, one scanline after another.
+///
+/// Samples are stored exactly as in the PNG data stream: packed most significant bits
+/// first for bit depths below 8, and big-endian for a bit depth of 16.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pixels {
+    header: ImageHeader,
+    palette: Option<Vec<[u8; 3]>>,
+    data: Vec<u8>,
+}
+
+impl Pixels {
+    /// Creates `Pixels` from unfiltered scanlines laid out as described by `header`.
+    ///
+    /// This function returns an error if `data` does not hold exactly `height` scanlines.
+    pub fn from_raw(
+        header: ImageHeader,
+        palette: Option<Vec<[u8; 3]>>,
+        data: Vec<u8>,
+    ) -> ProgramResult<Self> {
+        let expected = image_len(header.row_bytes(header.width()), header.height())?;
+        if data.len() != expected {
+            return Err(ProgramError::InvalidImageData(ImageDataError::Length {
+                expected,
+                actual: data.len(),
+            }));
+        }
+        Ok(Self {
+            header,
+            palette,
+            data,
+        })
+    }
+
+    /// Inflates the concatenated `IDAT` data in `zlib` and reverses the scanline filters.
+    ///
+    /// The seven passes of Adam7-interlaced images are mapped onto the full-resolution grid,
+    /// so pixels are addressed the same way whatever the interlace method.
+    pub fn decode(
+        header: ImageHeader,
+        palette: Option<Vec<[u8; 3]>>,
+        zlib: &[u8],
+    ) -> ProgramResult<Self> {
+        let layout = reduced_images(&header);
+        // Each scanline starts with its filter type byte
+        let lengths = layout
+            .iter()
+            .map(|h| image_len(h.row_bytes(h.width()) + 1, h.height()))
+            .collect::<ProgramResult<Vec<_>>>()?;
+        let expected = lengths
+            .iter()
+            .try_fold(0usize, |total, &len| total.checked_add(len))
+            .ok_or(ProgramError::InvalidImageData(ImageDataError::TooLarge))?;
+        let filtered = inflate(zlib, expected)?;
+
+        let mut offset = 0;
+        let mut images = Vec::with_capacity(layout.len());
+        for (reduced, len) in layout.into_iter().zip(lengths) {
+            let data = unfilter_image(&reduced, &filtered[offset..offset + len])?;
+            images.push(Self::from_raw(reduced, None, data)?);
+            offset += len;
+        }
+
+        if !header.is_interlaced() {
+            let image = images.remove(0);
+            return Self::from_raw(header, palette, image.data);
+        }
+
+        let mut pixels = Self::from_raw(
+            header,
+            palette,
+            vec![0u8; image_len(header.row_bytes(header.width()), header.height())?],
+        )?;
+        for (pass, image) in non_empty_passes(&header).zip(images.iter()) {
+            for y in 0..image.height() {
+                for x in 0..image.width() {
+                    let (fx, fy) = pass.to_full(x, y);
+                    pixels.copy_pixel(fx, fy, image, x, y);
+                }
+            }
+        }
+        Ok(pixels)
+    }
+
+    /// Applies scanline filters and deflates the result into a zlib stream for `IDAT` chunks.
+    ///
+    /// Images whose header is Adam7-interlaced are written as seven passes.
+    /// Each row gets the filter picked by `filter::best_filter`, except for indexed images
+    /// and bit depths below 8, which the PNG spec recommends leaving unfiltered.
+    pub fn encode(&self) -> ProgramResult<Vec<u8>> {
+        let mut filtered = Vec::new();
+        if self.header.is_interlaced() {
+            for (pass, reduced) in non_empty_passes(&self.header).zip(reduced_images(&self.header))
+            {
+                let row_bytes = reduced.row_bytes(reduced.width());
+                let mut image = Self::from_raw(
+                    reduced,
+                    None,
+                    vec![0u8; row_bytes * reduced.height() as usize],
+                )?;
+                for y in 0..image.height() {
+                    for x in 0..image.width() {
+                        let (fx, fy) = pass.to_full(x, y);
+                        image.copy_pixel(x, y, self, fx, fy);
+                    }
+                }
+                filter_image(&image, &mut filtered);
+            }
+        } else {
+            filter_image(self, &mut filtered);
+        }
+
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&filtered)?;
+        Ok(encoder.finish()?)
+    }
+
+    /// Switches the interlace method these pixels are written with.
+    ///
+    /// The pixel grid is the same either way; only the layout of the encoded data changes.
+    pub fn set_interlaced(&mut self, interlaced: bool) {
+        self.header = self.header.with_interlace(interlaced);
+    }
+
+    /// The `ImageHeader` describing the layout of these pixels
+    pub fn header(&self) -> &ImageHeader {
+        &self.header
+    }
+
+    /// Width of the image in pixels
+    pub fn width(&self) -> u32 {
+        self.header.width()
+    }
+
+    /// Height of the image in pixels
+    pub fn height(&self) -> u32 {
+        self.header.height()
+    }
+
+    /// The palette of an indexed image, if a `PLTE` chunk was present
+    pub fn palette(&self) -> Option<&[[u8; 3]]> {
+        self.palette.as_deref()
+    }
+
+    /// All unfiltered scanlines, without filter type bytes
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// The raw bytes of scanline `y`.
+    ///
+    /// Panics if `y` is out of bounds.
+    pub fn row(&self, y: u32) -> &[u8] {
+        let row_bytes = self.header.row_bytes(self.width());
+        &self.data[y as usize * row_bytes..(y as usize + 1) * row_bytes]
+    }
+
+    /// The raw bytes of scanline `y`, mutably.
+    ///
+    /// Panics if `y` is out of bounds.
+    pub fn row_mut(&mut self, y: u32) -> &mut [u8] {
+        let row_bytes = self.header.row_bytes(self.width());
+        &mut self.data[y as usize * row_bytes..(y as usize + 1) * row_bytes]
+    }
+
+    /// Returns sample `channel` of the pixel at (`x`, `y`).
+    ///
+    /// Panics if the pixel or channel is out of bounds.
+    pub fn sample(&self, x: u32, y: u32, channel: usize) -> u16 {
+        let (depth, index) = self.locate(x, channel);
+        let row = self.row(y);
+        match depth {
+            16 => u16::from_be_bytes([row[2 * index], row[2 * index + 1]]),
+            8 => row[index] as u16,
+            _ => {
+                let bit = index * depth;
+                let shift = 8 - depth - bit % 8;
+                ((row[bit / 8] >> shift) & ((1 << depth) - 1)) as u16
+            }
+        }
+    }
+
+    /// Overwrites sample `channel` of the pixel at (`x`, `y`) with `value`.
+    ///
+    /// Bits of `value` above the bit depth are ignored.
+    /// Panics if the pixel or channel is out of bounds.
+    pub fn set_sample(&mut self, x: u32, y: u32, channel: usize, value: u16) {
+        let (depth, index) = self.locate(x, channel);
+        let row = self.row_mut(y);
+        match depth {
+            16 => row[2 * index..2 * index + 2].copy_from_slice(&value.to_be_bytes()),
+            8 => row[index] = value as u8,
+            _ => {
+                let bit = index * depth;
+                let shift = 8 - depth - bit % 8;
+                let mask = (((1u16 << depth) - 1) as u8) << shift;
+                row[bit / 8] = (row[bit / 8] & !mask) | (((value as u8) << shift) & mask);
+            }
+        }
+    }
+
+    /// Returns all samples of the pixel at (`x`, `y`), in the channel order of its color type.
+    ///
+    /// Panics if the pixel is out of bounds.
+    pub fn pixel(&self, x: u32, y: u32) -> Vec<u16> {
+        (0..self.header.color_type().channels())
+            .map(|channel| self.sample(x, y, channel))
+            .collect()
+    }
+
+    /// Looks up the palette color of the pixel at (`x`, `y`) in an indexed image.
+    ///
+    /// Returns `None` for other color types, a missing palette or an out-of-range index.
+    /// Panics if the pixel is out of bounds.
+    pub fn palette_color(&self, x: u32, y: u32) -> Option<[u8; 3]> {
+        if self.header.color_type() != ColorType::Indexed {
+            return None;
+        }
+        let index = self.sample(x, y, 0) as usize;
+        self.palette()?.get(index).copied()
+    }
+
+    /// Copies the pixel at (`sx`, `sy`) of `src` to (`x`, `y`) of these pixels.
+    ///
+    /// Both images must share the same color type and bit depth.
+    fn copy_pixel(&mut self, x: u32, y: u32, src: &Self, sx: u32, sy: u32) {
+        let bits = self.header.bits_per_pixel();
+        if bits >= 8 {
+            let bytes = bits / 8;
+            let (from, to) = (sx as usize * bytes, x as usize * bytes);
+            self.row_mut(y)[to..to + bytes].copy_from_slice(&src.row(sy)[from..from + bytes]);
+        } else {
+            self.set_sample(x, y, 0, src.sample(sx, sy, 0));
+        }
+    }
+
+    /// Returns the bit depth and the index of the sample for `channel` of pixel `x` within its row.
+    fn locate(&self, x: u32, channel: usize) -> (usize, usize) {
+        let channels = self.header.color_type().channels();
+        assert!(
+            x < self.width() && channel < channels,
+            "pixel {} channel {} out of bounds",
+            x,
+            channel
+        );
+        (
+            self.header.bit_depth() as usize,
+            x as usize * channels + channel,
+        )
+    }
+}
+
+/// Returns the passes of `header` that hold at least one pixel
+fn non_empty_passes(header: &ImageHeader) -> impl Iterator<Item = Pass> + '_ {
+    PASSES.into_iter().filter(|pass| {
+        let (w, h) = pass.size(header.width(), header.height());
+        w > 0 && h > 0
+    })
+}
+
+/// Describes the images stored one after another in the data stream: the full image when it
+/// is not interlaced, or the reduced image of every non-empty Adam7 pass otherwise.
+fn reduced_images(header: &ImageHeader) -> Vec<ImageHeader> {
+    if !header.is_interlaced() {
+        return vec![*header];
+    }
+    non_empty_passes(header)
+        .map(|pass| {
+            let (w, h) = pass.size(header.width(), header.height());
+            header.with_size(w, h)
+        })
+        .collect()
+}
+
+/// Reverses the scanline filters of the image described by `header`.
+fn unfilter_image(header: &ImageHeader, filtered: &[u8]) -> ProgramResult<Vec<u8>> {
+    let row_bytes = header.row_bytes(header.width());
+    let bpp = header.filter_bpp();
+    let mut data = vec![0u8; image_len(row_bytes, header.height())?];
+    let mut prior = vec![0u8; row_bytes];
+    for (y, line) in filtered.chunks_exact(row_bytes + 1).enumerate() {
+        let filter = FilterType::try_from(line[0])?;
+        let row = &mut data[y * row_bytes..(y + 1) * row_bytes];
+        row.copy_from_slice(&line[1..]);
+        filter::unfilter(filter, row, &prior, bpp);
+        prior.copy_from_slice(row);
+    }
+    Ok(data)
+}
+
+/// Filters every scanline of `image` and appends the result to `out`.
+fn filter_image(image: &Pixels, out: &mut Vec<u8>) {
+    let header = image.header();
+    let row_bytes = header.row_bytes(header.width());
+    let bpp = header.filter_bpp();
+    let adaptive = header.color_type() != ColorType::Indexed && header.bit_depth() >= 8;
+
+    let zeros = vec![0u8; row_bytes];
+    let mut prior: &[u8] = &zeros;
+    for row in image.data().chunks_exact(row_bytes) {
+        let filter_type = if adaptive {
+            filter::best_filter(row, prior, bpp)
+        } else {
+            FilterType::None
+        };
+        filter::filter(filter_type, row, prior, bpp, out);
+        prior = row;
+    }
+}
+
+/// Parses the data of a `PLTE` chunk into RGB entries.
+pub fn parse_palette(data: &[u8]) -> ProgramResult<Vec<[u8; 3]>> {
+    if data.is_empty() || !data.len().is_multiple_of(3) || data.len() > 256 * 3 {
+        return Err(ProgramError::InvalidImageData(ImageDataError::Palette(
+            data.len(),
+        )));
+    }
+    Ok(data.chunks_exact(3).map(|c| [c[0], c[1], c[2]]).collect())
+}
+
+/// Returns the size of `height` scanlines of `row_bytes` bytes each.
+///
+/// Header dimensions come from untrusted files, so a product that overflows `usize`
+/// is reported as `ProgramError::InvalidImageData` rather than trusted.
+fn image_len(row_bytes: usize, height: u32) -> ProgramResult<usize> {
+    row_bytes
+        .checked_mul(height as usize)
+        .ok_or(ProgramError::InvalidImageData(ImageDataError::Scanlines {
+            height,
+            row_bytes,
+        }))
+}
+
+/// Inflates the zlib stream `zlib`, which must decompress to exactly `expected` bytes.
+///
+/// The buffer grows with the data actually inflated, so a header claiming huge
+/// dimensions costs no more memory than its zlib stream decompresses to.
+fn inflate(zlib: &[u8], expected: usize) -> ProgramResult<Vec<u8>> {
+    let mut inflated = Vec::new();
+    // Reading one byte past `expected` catches oversized streams without inflating all of them
+    let _ = ZlibDecoder::new(zlib)
+        .take(expected as u64 + 1)
+        .read_to_end(&mut inflated)
+        .map_err(|e| ProgramError::InvalidImageData(ImageDataError::Zlib(e)))?;
+    if inflated.len() != expected {
+        return Err(ProgramError::InvalidImageData(ImageDataError::Inflated {
+            expected,
+            actual: inflated.len(),
+        }));
+    }
+    Ok(inflated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::{write::ZlibEncoder, Compression};
+    use std::io::Write;
+
+    fn header(width: u32, height: u32, bit_depth: u8, color_type: u8) -> ImageHeader {
+        let data: Vec<u8> = width
+            .to_be_bytes()
+            .iter()
+            .chain(height.to_be_bytes().iter())
+            .chain([bit_depth, color_type, 0, 0, 0].iter())
+            .copied()
+            .collect();
+        ImageHeader::try_from(data.as_slice()).unwrap()
+    }
+
+    fn interlaced(width: u32, height: u32, bit_depth: u8, color_type: u8) -> ImageHeader {
+        header(width, height, bit_depth, color_type).with_interlace(true)
+    }
+
+    fn deflate(bytes: &[u8]) -> Vec<u8> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(bytes).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn test_decode_filtered_rows() {
+        // 2x5 RGB 8-bit image, every row using a different filter
+        #[rustfmt::skip]
+        let filtered = [
+            0, 1, 2, 3, 4, 5, 6,        // None:    1 2 3 | 4 5 6
+            1, 10, 20, 30, 1, 1, 1,     // Sub:     10 20 30 | 11 21 31
+            2, 1, 1, 1, 1, 1, 1,        // Up:      11 21 31 | 12 22 32
+            3, 5, 5, 5, 5, 5, 5,        // Average: 10 15 20 | 16 23 31
+            4, 1, 1, 1, 1, 1, 1,        // Paeth:   11 16 21 | 17 24 32
+        ];
+        let pixels = Pixels::decode(header(2, 5, 8, 2), None, &deflate(&filtered)).unwrap();
+
+        assert_eq!(pixels.row(0), [1, 2, 3, 4, 5, 6]);
+        assert_eq!(pixels.row(1), [10, 20, 30, 11, 21, 31]);
+        assert_eq!(pixels.row(2), [11, 21, 31, 12, 22, 32]);
+        assert_eq!(pixels.row(3), [10, 15, 20, 16, 23, 31]);
+        assert_eq!(pixels.row(4), [11, 16, 21, 17, 24, 32]);
+        assert_eq!(pixels.pixel(1, 3), [16, 23, 31]);
+    }
+
+    #[test]
+    fn test_packed_samples() {
+        // 5x1 grayscale 2-bit image: 0b00_01_10_11, 0b11_000000
+        let pixels = Pixels::decode(header(5, 1, 2, 0), None, &deflate(&[0, 0x1b, 0xc0])).unwrap();
+        let samples: Vec<u16> = (0..5).map(|x| pixels.sample(x, 0, 0)).collect();
+        assert_eq!(samples, [0, 1, 2, 3, 3]);
+    }
+
+    #[test]
+    fn test_set_packed_sample() {
+        let mut pixels = Pixels::from_raw(header(5, 1, 2, 0), None, vec![0x1b, 0xc0]).unwrap();
+        pixels.set_sample(1, 0, 0, 2);
+        pixels.set_sample(4, 0, 0, 0);
+        assert_eq!(pixels.row(0), [0x2b, 0x00]);
+    }
+
+    #[test]
+    fn test_sixteen_bit_samples() {
+        let mut pixels =
+            Pixels::from_raw(header(1, 1, 16, 4), None, vec![0x12, 0x34, 0xff, 0xfe]).unwrap();
+        assert_eq!(pixels.pixel(0, 0), [0x1234, 0xfffe]);
+        pixels.set_sample(0, 0, 1, 0xabcd);
+        assert_eq!(pixels.row(0), [0x12, 0x34, 0xab, 0xcd]);
+    }
+
+    #[test]
+    fn test_palette_lookup() {
+        let palette = parse_palette(&[255, 0, 0, 0, 255, 0]).unwrap();
+        let pixels = Pixels::from_raw(header(3, 1, 4, 3), Some(palette), vec![0x10, 0x20]).unwrap();
+        assert_eq!(pixels.palette_color(0, 0), Some([0, 255, 0]));
+        assert_eq!(pixels.palette_color(1, 0), Some([255, 0, 0]));
+        assert_eq!(pixels.palette_color(2, 0), None);
+    }
+
+    #[test]
+    fn test_encode_round_trip() {
+        let data: Vec<u8> = (0..6 * 4 * 3).map(|i| (i * 37 % 251) as u8).collect();
+        let pixels = Pixels::from_raw(header(6, 4, 8, 2), None, data).unwrap();
+        let decoded = Pixels::decode(header(6, 4, 8, 2), None, &pixels.encode().unwrap()).unwrap();
+        assert_eq!(decoded, pixels);
+    }
+
+    #[test]
+    fn test_decode_adam7() {
+        // 5x5 grayscale 8-bit image where each pixel holds y * 5 + x, unfiltered
+        #[rustfmt::skip]
+        let filtered = [
+            0, 0,                    // pass 1: (0,0)
+            0, 4,                    // pass 2: (4,0)
+            0, 20, 24,               // pass 3: (0,4) (4,4)
+            0, 2, 0, 22,             // pass 4: (2,0) / (2,4)
+            0, 10, 12, 14,           // pass 5: (0,2) (2,2) (4,2)
+            0, 1, 3, 0, 11, 13, 0, 21, 23,        // pass 6: odd columns of even rows
+            0, 5, 6, 7, 8, 9, 0, 15, 16, 17, 18, 19, // pass 7: odd rows
+        ];
+        let pixels = Pixels::decode(interlaced(5, 5, 8, 0), None, &deflate(&filtered)).unwrap();
+        let expected: Vec<u8> = (0..25).collect();
+        assert_eq!(pixels.data(), expected);
+    }
+
+    #[test]
+    fn test_adam7_round_trip() {
+        for (bit_depth, color_type) in [(8, 6), (16, 2), (1, 0), (4, 3)] {
+            let header = interlaced(11, 9, bit_depth, color_type);
+            let len = header.row_bytes(11) * 9;
+            let data: Vec<u8> = (0..len).map(|i| (i * 37 % 251) as u8).collect();
+            let mut pixels = Pixels::from_raw(header, None, data).unwrap();
+            // Clear the padding bits at the end of each row, which encoding drops
+            let row_bits = 11 * header.bits_per_pixel();
+            if !row_bits.is_multiple_of(8) {
+                for y in 0..9 {
+                    let last = pixels.row(y).len() - 1;
+                    pixels.row_mut(y)[last] &= 0xff << (8 - row_bits % 8);
+                }
+            }
+
+            let encoded = pixels.encode().unwrap();
+            assert_eq!(Pixels::decode(header, None, &encoded).unwrap(), pixels);
+        }
+    }
+
+    #[test]
+    fn test_deinterlace_on_write() {
+        let header = interlaced(7, 7, 8, 2);
+        let data: Vec<u8> = (0..7 * 7 * 3).map(|i| i as u8).collect();
+        let mut pixels = Pixels::from_raw(header, None, data).unwrap();
+        pixels.set_interlaced(false);
+        assert!(!pixels.header().is_interlaced());
+
+        let decoded = Pixels::decode(*pixels.header(), None, &pixels.encode().unwrap()).unwrap();
+        assert_eq!(decoded.data(), pixels.data());
+    }
+
+    #[test]
+    fn test_wrong_data_length() {
+        assert!(Pixels::decode(header(2, 2, 8, 0), None, &deflate(&[0, 1, 2])).is_err());
+        assert!(Pixels::from_raw(header(2, 2, 8, 0), None, vec![0; 5]).is_err());
+    }
+
+    #[test]
+    fn test_huge_dimensions() {
+        // 100000x100000 RGBA8 claims 40 GB of pixels; decoding must fail without allocating them
+        let huge = header(100_000, 100_000, 8, 6);
+        assert!(matches!(
+            Pixels::decode(huge, None, &deflate(&[0; 16])),
+            Err(ProgramError::InvalidImageData(ImageDataError::Inflated {
+                actual: 16,
+                ..
+            }))
+        ));
+        assert!(Pixels::from_raw(huge, None, vec![0; 16]).is_err());
+    }
+
+    #[test]
+    fn test_overflowing_dimensions() {
+        let max = 0x7fff_ffff;
+        for huge in [header(max, max, 16, 6), interlaced(max, max, 16, 6)] {
+            assert!(matches!(
+                Pixels::decode(huge, None, &deflate(&[0; 16])),
+                Err(ProgramError::InvalidImageData(
+                    ImageDataError::Scanlines { .. } | ImageDataError::TooLarge
+                ))
+            ));
+        }
+    }
+}
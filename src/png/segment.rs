@@ -0,0 +1,334 @@
+//! Implements `Segment`, a framing layer for payloads that span multiple chunks.
+
+use super::{
+    chunk::{Chunk, ChunkRef},
+    chunk_type::ChunkType,
+};
+use crate::{
+    error::{ProgramError, SegmentError},
+    utils::crc_checksum,
+    ProgramResult,
+};
+use std::collections::BTreeMap;
+
+/// Marks the start of a segment header inside chunk data
+pub const MAGIC: [u8; 4] = *b"imsg";
+
+/// Current version of the segment header layout
+pub const VERSION: u8 = 1;
+
+/// Length of a segment header in bytes
+pub const HEADER_LEN: usize = 21;
+
+/// Largest amount of payload data a single segment can carry
+pub const MAX_SEGMENT_SIZE: usize = super::chunk::MAX_LEN as usize - HEADER_LEN;
+
+/// One numbered piece of a payload, stored in the data of a single chunk.
+///
+/// The data of a segment chunk is laid out as follows:
+/// 1. Magic bytes `imsg` *(4 bytes)*
+/// 2. Header version *(1 byte)*
+/// 3. Payload id shared by all segments of the payload *(4 bytes)*
+/// 4. Sequence index of this segment *(4 bytes)*
+/// 5. Total number of segments in the payload *(4 bytes)*
+/// 6. Length of the segment data *(4 bytes)*
+/// 7. The segment data itself
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Segment {
+    payload_id: u32,
+    index: u32,
+    total: u32,
+    data: Vec<u8>,
+}
+
+impl Segment {
+    /// Splits `payload` into segments carrying at most `segment_size` bytes each.
+    ///
+    /// An empty payload yields a single empty segment.
+    pub fn split(payload: &[u8], segment_size: usize, payload_id: u32) -> ProgramResult<Vec<Self>> {
+        if segment_size == 0 || segment_size > MAX_SEGMENT_SIZE {
+            return Err(ProgramError::InvalidSegment(SegmentError::Size {
+                size: segment_size,
+                max: MAX_SEGMENT_SIZE,
+            }));
+        }
+
+        let pieces: Vec<&[u8]> = if payload.is_empty() {
+            vec![payload]
+        } else {
+            payload.chunks(segment_size).collect()
+        };
+        let total = u32::try_from(pieces.len())
+            .map_err(|_| ProgramError::InvalidSegment(SegmentError::TooMany))?;
+
+        Ok(pieces
+            .into_iter()
+            .enumerate()
+            .map(|(index, data)| Self {
+                payload_id,
+                index: index as u32,
+                total,
+                data: data.to_vec(),
+            })
+            .collect())
+    }
+
+    /// Puts the pieces of a single payload back together in order.
+    ///
+    /// Only segments sharing the payload id of the first segment are used.
+    /// This function returns an error if any segment is missing, duplicated
+    /// or disagrees with the others about the total count.
+    pub fn reassemble(segments: &[Self]) -> ProgramResult<Vec<u8>> {
+        let first = segments
+            .first()
+            .ok_or(ProgramError::InvalidSegment(SegmentError::Empty))?;
+        let (payload_id, total) = (first.payload_id, first.total);
+
+        let mut ordered = BTreeMap::new();
+        for segment in segments.iter().filter(|s| s.payload_id == payload_id) {
+            if segment.total != total || segment.index >= total {
+                return Err(ProgramError::InvalidSegment(SegmentError::Total {
+                    payload_id,
+                    index: segment.index,
+                    total: segment.total,
+                    expected: total,
+                }));
+            }
+            if ordered.insert(segment.index, segment.data()).is_some() {
+                return Err(ProgramError::DuplicateSegment {
+                    payload_id,
+                    index: segment.index,
+                });
+            }
+        }
+
+        if let Some(index) = (0..total).find(|i| !ordered.contains_key(i)) {
+            return Err(ProgramError::MissingSegment { payload_id, index });
+        }
+        Ok(ordered.into_values().flatten().copied().collect())
+    }
+
+    /// Returns true if `data` holds a well-formed segment: a header of the current
+    /// version whose index is below its total and whose declared length matches the
+    /// data that follows it
+    pub fn is_segment(data: &[u8]) -> bool {
+        if data.len() < HEADER_LEN || data[..4] != MAGIC || data[4] != VERSION {
+            return false;
+        }
+        let (index, total, len) = (field(data, 9), field(data, 13), field(data, 17));
+        index < total && len as usize == data.len() - HEADER_LEN
+    }
+
+    /// Identifies the payload this segment belongs to
+    pub fn payload_id(&self) -> u32 {
+        self.payload_id
+    }
+
+    /// The position of this segment within its payload
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+
+    /// The number of segments in the payload
+    pub fn total(&self) -> u32 {
+        self.total
+    }
+
+    /// The piece of the payload carried by this segment
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Wraps this segment into a `Chunk` of the given type
+    pub fn to_chunk(&self, chunk_type: ChunkType) -> Chunk {
+        let data = MAGIC
+            .iter()
+            .chain([VERSION].iter())
+            .chain(self.payload_id.to_be_bytes().iter())
+            .chain(self.index.to_be_bytes().iter())
+            .chain(self.total.to_be_bytes().iter())
+            .chain((self.data.len() as u32).to_be_bytes().iter())
+            .chain(self.data.iter())
+            .copied()
+            .collect();
+        Chunk::new(chunk_type, data)
+    }
+}
+
+impl TryFrom<&Chunk> for Segment {
+    type Error = ProgramError;
+
+    fn try_from(chunk: &Chunk) -> ProgramResult<Self> {
+        Self::try_from(&chunk.as_chunk_ref())
+    }
+}
+
+impl TryFrom<&ChunkRef<'_>> for Segment {
+    type Error = ProgramError;
+
+    fn try_from(chunk: &ChunkRef<'_>) -> ProgramResult<Self> {
+        let data = chunk.data();
+        if !Self::is_segment(data) {
+            return Err(ProgramError::InvalidSegment(SegmentError::NoHeader {
+                chunk_type: chunk.chunk_type().to_string(),
+            }));
+        }
+
+        Ok(Self {
+            payload_id: field(data, 5),
+            index: field(data, 9),
+            total: field(data, 13),
+            data: data[HEADER_LEN..].to_vec(),
+        })
+    }
+}
+
+/// Reads the big-endian `u32` header field starting at `at`
+fn field(data: &[u8], at: usize) -> u32 {
+    u32::from_be_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]])
+}
+
+/// Splits `payload` into segments of at most `segment_size` bytes and wraps each
+/// in a `chunk_type` chunk, in order.
+///
+/// Returns the id shared by all segments of the payload (the CRC of the payload) and the chunks.
+pub fn to_chunks(
+    chunk_type: &ChunkType,
+    payload: &[u8],
+    segment_size: usize,
+) -> ProgramResult<(u32, Vec<Chunk>)> {
+    let payload_id = crc_checksum(payload);
+    let chunks = Segment::split(payload, segment_size, payload_id)?
+        .iter()
+        .map(|s| s.to_chunk(chunk_type.clone()))
+        .collect();
+    Ok((payload_id, chunks))
+}
+
+/// Returns the payload stored in the `chunk_type` chunks among `chunks`.
+///
+/// Segmented payloads are reassembled in order; otherwise the data of the first
+/// matching chunk is returned as-is.
+pub fn payload<'a>(
+    chunk_type: &ChunkType,
+    chunks: impl IntoIterator<Item = ChunkRef<'a>>,
+) -> ProgramResult<Vec<u8>> {
+    let chunks: Vec<ChunkRef<'a>> = chunks
+        .into_iter()
+        .filter(|c| c.chunk_type() == chunk_type)
+        .collect();
+    let first = chunks.first().ok_or_else(|| ProgramError::ChunkNotFound {
+        chunk_type: chunk_type.to_string(),
+        offset: None,
+    })?;
+    if !Segment::is_segment(first.data()) {
+        return Ok(first.data().to_vec());
+    }
+
+    let segments = chunks
+        .into_iter()
+        .filter(|c| Segment::is_segment(c.data()))
+        .map(|c| Segment::try_from(&c))
+        .collect::<ProgramResult<Vec<Segment>>>()?;
+    Segment::reassemble(&segments)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    const PAYLOAD: &[u8] = b"This is where your secret message will be!";
+
+    #[test]
+    fn test_split_sizes() {
+        let segments = Segment::split(PAYLOAD, 10, 7).unwrap();
+        assert_eq!(segments.len(), 5);
+        assert!(segments
+            .iter()
+            .all(|s| s.total() == 5 && s.payload_id() == 7));
+        assert_eq!(segments[4].data(), b"e!");
+    }
+
+    #[test]
+    fn test_split_empty_payload() {
+        let segments = Segment::split(&[], 10, 7).unwrap();
+        assert_eq!(segments.len(), 1);
+        assert_eq!(Segment::reassemble(&segments).unwrap(), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn test_split_invalid_size() {
+        assert!(Segment::split(PAYLOAD, 0, 7).is_err());
+    }
+
+    #[test]
+    fn test_reassemble_out_of_order() {
+        let mut segments = Segment::split(PAYLOAD, 4, 7).unwrap();
+        segments.reverse();
+        assert_eq!(Segment::reassemble(&segments).unwrap(), PAYLOAD);
+    }
+
+    #[test]
+    fn test_reassemble_missing_segment() {
+        let mut segments = Segment::split(PAYLOAD, 4, 7).unwrap();
+        let _ = segments.remove(3);
+        assert!(matches!(
+            Segment::reassemble(&segments),
+            Err(ProgramError::MissingSegment {
+                payload_id: 7,
+                index: 3
+            })
+        ));
+    }
+
+    #[test]
+    fn test_reassemble_duplicate_segment() {
+        let mut segments = Segment::split(PAYLOAD, 4, 7).unwrap();
+        segments.push(segments[2].clone());
+        assert!(matches!(
+            Segment::reassemble(&segments),
+            Err(ProgramError::DuplicateSegment {
+                payload_id: 7,
+                index: 2
+            })
+        ));
+    }
+
+    #[test]
+    fn test_segment_chunk_round_trip() {
+        let chunk_type = ChunkType::from_str("RuSt").unwrap();
+        let segment = Segment::split(PAYLOAD, 100, 7).unwrap().remove(0);
+        let chunk = segment.to_chunk(chunk_type);
+        assert_eq!(chunk.length() as usize, HEADER_LEN + PAYLOAD.len());
+        assert_eq!(Segment::try_from(&chunk).unwrap(), segment);
+    }
+
+    #[test]
+    fn test_malformed_header_is_not_segment() {
+        let chunk_type = ChunkType::from_str("RuSt").unwrap();
+        let segment = Segment::split(PAYLOAD, 100, 7).unwrap().remove(0);
+        let data = segment.to_chunk(chunk_type).data().to_vec();
+        assert!(Segment::is_segment(&data));
+
+        let mut version = data.clone();
+        version[4] = 9;
+        assert!(!Segment::is_segment(&version));
+
+        let mut index = data.clone();
+        index[9..13].copy_from_slice(&1u32.to_be_bytes());
+        assert!(!Segment::is_segment(&index));
+
+        assert!(!Segment::is_segment(&data[..data.len() - 1]));
+        let mut longer = data;
+        longer.push(0);
+        assert!(!Segment::is_segment(&longer));
+    }
+
+    #[test]
+    fn test_plain_chunk_is_not_segment() {
+        let chunk_type = ChunkType::from_str("RuSt").unwrap();
+        let chunk = Chunk::new(chunk_type, PAYLOAD.to_vec());
+        assert!(Segment::try_from(&chunk).is_err());
+    }
+}
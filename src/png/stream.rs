@@ -0,0 +1,421 @@
+//! Reads and writes PNG files one chunk at a time.
+//!
+//! Unlike `Png`, which holds every chunk in memory, `ChunkReader` and `ChunkWriter`
+//! only hold the chunk being processed, so large files can be copied from input
+//! to output with bounded memory.
+
+use super::{
+    chunk::Chunk, chunk_type::ChunkType, trailing_chunk_range, ChunkPosition, ParseOptions, Png,
+    Strictness,
+};
+use crate::{error::ProgramError, ProgramResult};
+use core::str::FromStr;
+use std::io::{self, ErrorKind, Read, Write};
+
+/// An iterator over the chunks of a PNG file read from `R`.
+///
+/// The signature is checked when the reader is created. Iteration ends after the
+/// `IEND` chunk (or at the end of the input); anything after it is left in the
+/// underlying reader, see `ChunkReader::into_inner` and `ChunkReader::into_trailing_chunks`.
+#[derive(Debug)]
+pub struct ChunkReader<R> {
+    reader: R,
+    options: ParseOptions,
+    diagnostics: Vec<ProgramError>,
+    offset: usize,
+    past_iend: bool,
+    finished: bool,
+}
+
+impl<R: Read> ChunkReader<R> {
+    /// Creates a reader that rejects the same files as `Png::try_from`.
+    pub fn new(reader: R) -> ProgramResult<Self> {
+        Self::with_options(reader, ParseOptions::default())
+    }
+
+    /// Creates a reader that handles damaged files as `Png::parse` does with `options`.
+    ///
+    /// Problems that `options` lets through are collected in `ChunkReader::diagnostics`.
+    pub fn with_options(mut reader: R, options: ParseOptions) -> ProgramResult<Self> {
+        let mut header = [0; 8];
+        reader.read_exact(&mut header).map_err(|e| match e.kind() {
+            ErrorKind::UnexpectedEof => ProgramError::Truncated { offset: 0 },
+            _ => ProgramError::Io {
+                source: e,
+                offset: Some(0),
+            },
+        })?;
+        let mut diagnostics = Vec::new();
+        if header != Png::STANDARD_HEADER {
+            let error = ProgramError::BadSignature { found: header };
+            options.signature.report(error, &mut diagnostics)?;
+        }
+
+        Ok(Self {
+            reader,
+            options,
+            diagnostics,
+            offset: header.len(),
+            past_iend: false,
+            finished: false,
+        })
+    }
+
+    /// The number of bytes of the input consumed so far
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// The problems found so far that the `ParseOptions` of this reader let through
+    pub fn diagnostics(&self) -> &[ProgramError] {
+        &self.diagnostics
+    }
+
+    /// Returns the underlying reader.
+    ///
+    /// Once iteration has ended after `IEND`, the rest of the reader is the trailing data.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    /// Returns an iterator over the complete, valid chunks stored after `IEND`,
+    /// like those found by `Png::payload`.
+    ///
+    /// Iteration stops silently at the first byte that does not start such a chunk.
+    pub fn into_trailing_chunks(self) -> Self {
+        Self {
+            past_iend: true,
+            finished: false,
+            ..self
+        }
+    }
+}
+
+impl<R: Read> Iterator for ChunkReader<R> {
+    type Item = ProgramResult<Chunk>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+
+        let chunk = Chunk::read_unchecked(&mut self.reader).and_then(|chunk| {
+            if let Some(chunk) = &chunk {
+                if let Some(e) = chunk.crc_error() {
+                    let crc = if self.past_iend {
+                        Strictness::Strict
+                    } else {
+                        self.options.crc
+                    };
+                    crc.report(e, &mut self.diagnostics)?;
+                }
+            }
+            Ok(chunk)
+        });
+        match chunk {
+            Ok(Some(chunk)) => {
+                // `length`, `chunk_type` and `crc` take 12 bytes next to the data
+                self.offset += 12 + chunk.length() as usize;
+                self.finished = !self.past_iend && chunk.chunk_type().bytes() == *b"IEND";
+                Some(Ok(chunk))
+            }
+            Ok(None) => {
+                self.finished = true;
+                None
+            }
+            Err(_) if self.past_iend => {
+                self.finished = true;
+                None
+            }
+            Err(e) => {
+                self.finished = true;
+                Some(Err(e.offset_by(self.offset)))
+            }
+        }
+    }
+}
+
+/// Writes a PNG file to `W` one chunk at a time.
+///
+/// The standard signature is written when the writer is created.
+#[derive(Debug)]
+pub struct ChunkWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> ChunkWriter<W> {
+    /// Creates a writer and writes `Png::STANDARD_HEADER` to it.
+    pub fn new(mut writer: W) -> ProgramResult<Self> {
+        writer.write_all(&Png::STANDARD_HEADER)?;
+        Ok(Self { writer })
+    }
+
+    /// Writes `chunk` as the byte sequence described by `Chunk::as_bytes`.
+    pub fn write_chunk(&mut self, chunk: &Chunk) -> ProgramResult<()> {
+        self.writer.write_all(&chunk.length().to_be_bytes())?;
+        self.writer.write_all(&chunk.chunk_type().bytes())?;
+        self.writer.write_all(chunk.data())?;
+        self.writer.write_all(&chunk.crc().to_be_bytes())?;
+        Ok(())
+    }
+
+    /// Copies everything left in `data` to the output as-is, returning the number of bytes copied.
+    ///
+    /// Meant for the trailing data after `IEND`.
+    pub fn write_trailing<T: Read>(&mut self, mut data: T) -> ProgramResult<u64> {
+        Ok(io::copy(&mut data, &mut self.writer)?)
+    }
+
+    /// Flushes the output and returns the underlying writer.
+    pub fn finish(mut self) -> ProgramResult<W> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+/// Copies a PNG file from `reader` to `writer`, inserting `chunks` at `position`.
+///
+/// This is the streaming counterpart of `Png::insert_chunk`, and fails the same way
+/// if the chunk that `position` is relative to cannot be found.
+pub fn insert<R: Read, W: Write>(
+    mut reader: ChunkReader<R>,
+    writer: &mut ChunkWriter<W>,
+    chunks: &[Chunk],
+    position: ChunkPosition,
+) -> ProgramResult<()> {
+    let mut pending = Some(chunks);
+    let mut write_pending = |writer: &mut ChunkWriter<W>| {
+        pending
+            .take()
+            .into_iter()
+            .flatten()
+            .try_for_each(|c| writer.write_chunk(c))
+    };
+
+    for chunk in reader.by_ref() {
+        let chunk = chunk?;
+        let chunk_type = chunk.chunk_type().bytes();
+        match (position, &chunk_type) {
+            (ChunkPosition::BeforeIend, b"IEND") | (ChunkPosition::BeforeIdat, b"IDAT") => {
+                write_pending(writer)?
+            }
+            _ => {}
+        }
+        writer.write_chunk(&chunk)?;
+        if position == ChunkPosition::AfterIhdr && chunk_type == *b"IHDR" {
+            write_pending(writer)?;
+        }
+    }
+    if position == ChunkPosition::End {
+        write_pending(writer)?;
+    }
+
+    if pending.is_some() {
+        let anchor = match position {
+            ChunkPosition::BeforeIend => "IEND",
+            ChunkPosition::AfterIhdr => "IHDR",
+            ChunkPosition::BeforeIdat => "IDAT",
+            ChunkPosition::End => unreachable!("chunks placed at the end are always written"),
+        };
+        return Err(ProgramError::ChunkNotFound {
+            chunk_type: anchor.to_string(),
+            offset: Some(reader.offset()),
+        });
+    }
+    let _ = writer.write_trailing(reader.into_inner())?;
+    Ok(())
+}
+
+/// Copies a PNG file from `reader` to `writer`, leaving out the first chunk of type `chunk_type`.
+///
+/// This is the streaming counterpart of `Png::remove_chunk`; it returns the removed chunk,
+/// or `ProgramError::ChunkNotFound` if the file holds no such chunk. When none comes
+/// before `IEND`, the trailing data is read into memory to look for one there.
+pub fn remove<R: Read, W: Write>(
+    mut reader: ChunkReader<R>,
+    writer: &mut ChunkWriter<W>,
+    chunk_type: &str,
+) -> ProgramResult<Chunk> {
+    let mut removed = None;
+    for chunk in reader.by_ref() {
+        let chunk = chunk?;
+        if removed.is_none() && chunk.chunk_type().to_string() == chunk_type {
+            removed = Some(chunk);
+        } else {
+            writer.write_chunk(&chunk)?;
+        }
+    }
+    let mut end = reader.offset();
+    let mut trailing = reader.into_inner();
+    if removed.is_none() {
+        // Chunks written after `IEND` are read back as trailing data, as in `Png::remove_chunk`
+        let mut data = Vec::new();
+        end += trailing.read_to_end(&mut data)?;
+        let chunk_type = ChunkType::from_str(chunk_type)?;
+        if let Some(range) = trailing_chunk_range(&data, &chunk_type) {
+            removed = Some(Chunk::try_from(&data[range.clone()])?);
+            let _ = data.drain(range);
+        }
+        let _ = writer.write_trailing(data.as_slice())?;
+    } else {
+        let _ = writer.write_trailing(trailing)?;
+    }
+    removed.ok_or_else(|| ProgramError::ChunkNotFound {
+        chunk_type: chunk_type.to_string(),
+        offset: Some(end),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::DICE_PNG;
+    use std::convert::TryFrom;
+
+    fn testing_chunk() -> Chunk {
+        Chunk::new(ChunkType::from_str("TeSt").unwrap(), b"Message".to_vec())
+    }
+
+    fn copy_with<F>(bytes: &[u8], f: F) -> Vec<u8>
+    where
+        F: FnOnce(ChunkReader<&[u8]>, &mut ChunkWriter<Vec<u8>>) -> ProgramResult<()>,
+    {
+        let reader = ChunkReader::new(bytes).unwrap();
+        let mut writer = ChunkWriter::new(Vec::new()).unwrap();
+        f(reader, &mut writer).unwrap();
+        writer.finish().unwrap()
+    }
+
+    #[test]
+    fn test_read_chunks() {
+        let png = Png::try_from(DICE_PNG.as_slice()).unwrap();
+        let mut reader = ChunkReader::new(DICE_PNG.as_slice()).unwrap();
+        let chunks: Vec<Chunk> = reader.by_ref().collect::<ProgramResult<_>>().unwrap();
+        assert_eq!(chunks, png.chunks());
+        assert_eq!(reader.offset(), DICE_PNG.len());
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let bytes: Vec<u8> = DICE_PNG.iter().chain(b"stray").copied().collect();
+        let copy = copy_with(&bytes, |mut reader, writer| {
+            for chunk in reader.by_ref() {
+                writer.write_chunk(&chunk?)?;
+            }
+            assert_eq!(writer.write_trailing(reader.into_inner())?, 5);
+            Ok(())
+        });
+        assert_eq!(copy, bytes);
+    }
+
+    #[test]
+    fn test_read_errors_carry_file_offsets() {
+        let mut bytes = DICE_PNG.to_vec();
+        bytes[32] ^= 0xff;
+        let first = ChunkReader::new(bytes.as_slice()).unwrap().next().unwrap();
+        assert_eq!(first.unwrap_err().offset(), Some(29));
+
+        let truncated = &DICE_PNG[..DICE_PNG.len() - 6];
+        let last = ChunkReader::new(truncated).unwrap().last().unwrap();
+        assert!(matches!(last, Err(ProgramError::Truncated { .. })));
+
+        assert!(matches!(
+            ChunkReader::new(&DICE_PNG[..4]),
+            Err(ProgramError::Truncated { offset: 0 })
+        ));
+
+        // A read error, unlike the end of the input, is reported as I/O at the chunk it hit
+        let failing = DICE_PNG[..33].chain(FailingReader);
+        let mut reader = ChunkReader::new(failing).unwrap();
+        assert!(reader.next().unwrap().is_ok());
+        let e = reader.next().unwrap().unwrap_err();
+        assert!(matches!(e, ProgramError::Io { .. }));
+        assert_eq!(e.offset(), Some(33));
+    }
+
+    struct FailingReader;
+
+    impl Read for FailingReader {
+        fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+            Err(io::Error::from(ErrorKind::PermissionDenied))
+        }
+    }
+
+    #[test]
+    fn test_lenient_reader() {
+        let mut bytes = DICE_PNG.to_vec();
+        bytes[32] ^= 0xff;
+        let options = ParseOptions {
+            crc: Strictness::Warn,
+            ..Default::default()
+        };
+        let mut reader = ChunkReader::with_options(bytes.as_slice(), options).unwrap();
+        assert!(reader.by_ref().all(|c| c.is_ok()));
+        assert_eq!(reader.diagnostics().len(), 1);
+    }
+
+    #[test]
+    fn test_trailing_chunks() {
+        let mut png = Png::try_from(DICE_PNG.as_slice()).unwrap();
+        png.insert_chunk(testing_chunk(), ChunkPosition::End)
+            .unwrap();
+        png.set_trailing_data([png.trailing_data(), b"junk"].concat());
+        let bytes = png.as_bytes();
+
+        let mut reader = ChunkReader::new(bytes.as_slice()).unwrap();
+        assert!(reader.by_ref().all(|c| c.is_ok()));
+        let trailing: Vec<_> = reader.into_trailing_chunks().collect();
+        assert_eq!(trailing.len(), 1);
+        assert_eq!(trailing[0].as_ref().unwrap(), &testing_chunk());
+    }
+
+    #[test]
+    fn test_insert_matches_png() {
+        for position in [
+            ChunkPosition::BeforeIend,
+            ChunkPosition::AfterIhdr,
+            ChunkPosition::BeforeIdat,
+            ChunkPosition::End,
+        ] {
+            let mut png = Png::try_from(DICE_PNG.as_slice()).unwrap();
+            png.insert_chunk(testing_chunk(), position).unwrap();
+            let copy = copy_with(&DICE_PNG, |reader, writer| {
+                insert(reader, writer, &[testing_chunk()], position)
+            });
+            assert_eq!(copy, png.as_bytes());
+        }
+    }
+
+    #[test]
+    fn test_remove_matches_png() {
+        let mut png = Png::try_from(DICE_PNG.as_slice()).unwrap();
+        let expected = png.remove_chunk("RuSt").unwrap();
+        let copy = copy_with(&DICE_PNG, |reader, writer| {
+            assert_eq!(remove(reader, writer, "RuSt")?, expected);
+            Ok(())
+        });
+        assert_eq!(copy, png.as_bytes());
+
+        let reader = ChunkReader::new(DICE_PNG.as_slice()).unwrap();
+        let mut writer = ChunkWriter::new(Vec::new()).unwrap();
+        let e = remove(reader, &mut writer, "NoPe").unwrap_err();
+        assert!(matches!(e, ProgramError::ChunkNotFound { .. }));
+        assert_eq!(e.offset(), Some(DICE_PNG.len()));
+    }
+
+    #[test]
+    fn test_remove_chunk_at_end() {
+        let encoded = copy_with(&DICE_PNG, |reader, writer| {
+            insert(reader, writer, &[testing_chunk()], ChunkPosition::End)
+        });
+        let removed = copy_with(&encoded, |reader, writer| {
+            assert_eq!(remove(reader, writer, "TeSt")?, testing_chunk());
+            Ok(())
+        });
+        assert_eq!(removed, DICE_PNG);
+        assert!(matches!(
+            Png::try_from(removed.as_slice()).unwrap().payload("TeSt"),
+            Err(ProgramError::ChunkNotFound { .. })
+        ));
+    }
+}
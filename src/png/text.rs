@@ -0,0 +1,903 @@
+//! Implements the textual metadata chunks described by the PNG specification.
+//! http://www.libpng.org/pub/png/spec/1.2/PNG-Chunks.html#C.Anc-text
+
+use super::{
+    chunk::{Chunk, ChunkRef},
+    chunk_type::ChunkType,
+    ChunkPosition, Png,
+};
+use crate::{error::ProgramError, ProgramResult};
+use core::str::FromStr;
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+use std::io::{Read, Write};
+
+/// Longest keyword allowed, in bytes
+pub const MAX_KEYWORD_LEN: usize = 79;
+
+/// Longest compressed text that is inflated, in bytes, so that a small chunk cannot
+/// exhaust memory by inflating to gigabytes
+pub const MAX_TEXT_LEN: usize = 8 << 20;
+
+/// The compression method byte of zlib (deflate), the only method the spec defines
+pub const COMPRESSION_ZLIB: u8 = 0;
+
+/// A `tEXt` chunk: a keyword and a Latin-1 text string.
+///
+/// The data of a `tEXt` chunk is laid out as follows:
+/// 1. Keyword *(1-79 bytes)*
+/// 2. Null separator *(1 byte)*
+/// 3. Text *(0 or more bytes)*
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextChunk {
+    keyword: String,
+    text: String,
+}
+
+impl TextChunk {
+    /// The chunk type of `tEXt` chunks
+    pub const CHUNK_TYPE: &'static str = "tEXt";
+
+    /// Creates a `tEXt` entry.
+    ///
+    /// This function returns `ProgramError::InvalidText` if the keyword is not a valid
+    /// keyword or either string holds characters outside Latin-1.
+    pub fn new(keyword: &str, text: &str) -> ProgramResult<Self> {
+        validate_keyword(keyword)?;
+        let _ = latin1_encode(text, "text")?;
+        if text.contains('\0') {
+            return Err(invalid("text", "must not contain null characters"));
+        }
+        Ok(Self {
+            keyword: keyword.to_string(),
+            text: text.to_string(),
+        })
+    }
+
+    /// The keyword naming this entry, such as "Title" or "Author"
+    pub fn keyword(&self) -> &str {
+        &self.keyword
+    }
+
+    /// The text of this entry
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Encodes this entry as a `tEXt` chunk.
+    pub fn to_chunk(&self) -> Chunk {
+        let data = latin1_bytes(&self.keyword)
+            .chain([0])
+            .chain(latin1_bytes(&self.text))
+            .collect();
+        Chunk::new(ChunkType::from_str(Self::CHUNK_TYPE).unwrap(), data)
+    }
+}
+
+impl TryFrom<&ChunkRef<'_>> for TextChunk {
+    type Error = ProgramError;
+
+    fn try_from(chunk: &ChunkRef<'_>) -> ProgramResult<Self> {
+        check_type(chunk, Self::CHUNK_TYPE)?;
+        let (keyword, text) = split_keyword(chunk.data())?;
+        if text.contains(&0) {
+            return Err(invalid("text", "must not contain null characters"));
+        }
+        Ok(Self {
+            keyword,
+            text: latin1_decode(text),
+        })
+    }
+}
+
+impl TryFrom<&Chunk> for TextChunk {
+    type Error = ProgramError;
+
+    fn try_from(chunk: &Chunk) -> ProgramResult<Self> {
+        Self::try_from(&chunk.as_chunk_ref())
+    }
+}
+
+impl core::fmt::Display for TextChunk {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.keyword, self.text)
+    }
+}
+
+/// A `zTXt` chunk: a keyword and a zlib-compressed Latin-1 text string.
+///
+/// The data of a `zTXt` chunk is laid out as follows:
+/// 1. Keyword *(1-79 bytes)*
+/// 2. Null separator *(1 byte)*
+/// 3. Compression method *(1 byte)*, always `COMPRESSION_ZLIB`
+/// 4. Compressed text *(0 or more bytes)*
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompressedTextChunk {
+    keyword: String,
+    text: String,
+}
+
+impl CompressedTextChunk {
+    /// The chunk type of `zTXt` chunks
+    pub const CHUNK_TYPE: &'static str = "zTXt";
+
+    /// Creates a `zTXt` entry.
+    ///
+    /// The rules are those of `TextChunk::new`, and the text must also fit in
+    /// `MAX_TEXT_LEN` bytes so that it can be inflated again.
+    pub fn new(keyword: &str, text: &str) -> ProgramResult<Self> {
+        let TextChunk { keyword, text } = TextChunk::new(keyword, text)?;
+        check_text_len(text.chars().count())?;
+        Ok(Self { keyword, text })
+    }
+
+    /// The keyword naming this entry, such as "Title" or "Author"
+    pub fn keyword(&self) -> &str {
+        &self.keyword
+    }
+
+    /// The decompressed text of this entry
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Encodes this entry as a `zTXt` chunk, deflating the text.
+    pub fn to_chunk(&self) -> ProgramResult<Chunk> {
+        let text: Vec<u8> = latin1_bytes(&self.text).collect();
+        let data = latin1_bytes(&self.keyword)
+            .chain([0, COMPRESSION_ZLIB])
+            .chain(deflate(&text)?)
+            .collect();
+        Ok(Chunk::new(
+            ChunkType::from_str(Self::CHUNK_TYPE).unwrap(),
+            data,
+        ))
+    }
+}
+
+impl TryFrom<&ChunkRef<'_>> for CompressedTextChunk {
+    type Error = ProgramError;
+
+    fn try_from(chunk: &ChunkRef<'_>) -> ProgramResult<Self> {
+        check_type(chunk, Self::CHUNK_TYPE)?;
+        let (keyword, rest) = split_keyword(chunk.data())?;
+        let (&method, compressed) = rest
+            .split_first()
+            .ok_or_else(|| invalid("compression method", "is missing"))?;
+        check_compression_method(method)?;
+        let text = inflate(compressed)?;
+        if text.contains(&0) {
+            return Err(invalid("text", "must not contain null characters"));
+        }
+        Ok(Self {
+            keyword,
+            text: latin1_decode(&text),
+        })
+    }
+}
+
+impl TryFrom<&Chunk> for CompressedTextChunk {
+    type Error = ProgramError;
+
+    fn try_from(chunk: &Chunk) -> ProgramResult<Self> {
+        Self::try_from(&chunk.as_chunk_ref())
+    }
+}
+
+impl core::fmt::Display for CompressedTextChunk {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.keyword, self.text)
+    }
+}
+
+/// An `iTXt` chunk: a keyword and a UTF-8 text string, optionally compressed,
+/// with the language of the text and the keyword translated into it.
+///
+/// The data of an `iTXt` chunk is laid out as follows:
+/// 1. Keyword *(1-79 bytes)*
+/// 2. Null separator *(1 byte)*
+/// 3. Compression flag *(1 byte)*, 1 if the text is compressed and 0 otherwise
+/// 4. Compression method *(1 byte)*, always `COMPRESSION_ZLIB`
+/// 5. Language tag *(0 or more bytes)*, such as "ja" or "en-GB"
+/// 6. Null separator *(1 byte)*
+/// 7. Translated keyword *(0 or more bytes)*, in UTF-8
+/// 8. Null separator *(1 byte)*
+/// 9. Text *(0 or more bytes)*, in UTF-8 and deflated if the flag is set
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InternationalTextChunk {
+    keyword: String,
+    compressed: bool,
+    language_tag: String,
+    translated_keyword: String,
+    text: String,
+}
+
+impl InternationalTextChunk {
+    /// The chunk type of `iTXt` chunks
+    pub const CHUNK_TYPE: &'static str = "iTXt";
+
+    /// Creates an `iTXt` entry, stored deflated if `compressed` is true.
+    ///
+    /// This function returns `ProgramError::InvalidText` if the keyword is not a valid
+    /// keyword, the language tag is not made of hyphen-separated words of 1-8 ASCII
+    /// letters or digits, any string holds a null character, or a compressed text is
+    /// longer than `MAX_TEXT_LEN` bytes.
+    pub fn new(
+        keyword: &str,
+        compressed: bool,
+        language_tag: &str,
+        translated_keyword: &str,
+        text: &str,
+    ) -> ProgramResult<Self> {
+        validate_keyword(keyword)?;
+        validate_language_tag(language_tag)?;
+        if translated_keyword.contains('\0') {
+            return Err(invalid(
+                "translated keyword",
+                "must not contain null characters",
+            ));
+        }
+        if text.contains('\0') {
+            return Err(invalid("text", "must not contain null characters"));
+        }
+        if compressed {
+            check_text_len(text.len())?;
+        }
+        Ok(Self {
+            keyword: keyword.to_string(),
+            compressed,
+            language_tag: language_tag.to_string(),
+            translated_keyword: translated_keyword.to_string(),
+            text: text.to_string(),
+        })
+    }
+
+    /// The keyword naming this entry, such as "Title" or "Author"
+    pub fn keyword(&self) -> &str {
+        &self.keyword
+    }
+
+    /// Whether the text is stored deflated
+    pub fn is_compressed(&self) -> bool {
+        self.compressed
+    }
+
+    /// The language of the text, such as "ja" or "en-GB", or "" if unknown
+    pub fn language_tag(&self) -> &str {
+        &self.language_tag
+    }
+
+    /// The keyword translated into the language of the text, or "" if there is none
+    pub fn translated_keyword(&self) -> &str {
+        &self.translated_keyword
+    }
+
+    /// The decompressed text of this entry
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Encodes this entry as an `iTXt` chunk, deflating the text if it is compressed.
+    pub fn to_chunk(&self) -> ProgramResult<Chunk> {
+        let text = if self.compressed {
+            deflate(self.text.as_bytes())?
+        } else {
+            self.text.as_bytes().to_vec()
+        };
+        let data = latin1_bytes(&self.keyword)
+            .chain([0, u8::from(self.compressed), COMPRESSION_ZLIB])
+            .chain(self.language_tag.bytes())
+            .chain([0])
+            .chain(self.translated_keyword.bytes())
+            .chain([0])
+            .chain(text)
+            .collect();
+        Ok(Chunk::new(
+            ChunkType::from_str(Self::CHUNK_TYPE).unwrap(),
+            data,
+        ))
+    }
+}
+
+impl TryFrom<&ChunkRef<'_>> for InternationalTextChunk {
+    type Error = ProgramError;
+
+    fn try_from(chunk: &ChunkRef<'_>) -> ProgramResult<Self> {
+        check_type(chunk, Self::CHUNK_TYPE)?;
+        let (keyword, rest) = split_keyword(chunk.data())?;
+        let [flag, method, rest @ ..] = rest else {
+            return Err(invalid("compression flag", "is missing"));
+        };
+        let compressed = match flag {
+            0 => false,
+            1 => true,
+            _ => {
+                return Err(invalid(
+                    "compression flag",
+                    format!("expected 0 or 1, received {}", flag),
+                ))
+            }
+        };
+        if compressed {
+            check_compression_method(*method)?;
+        }
+
+        let mut fields = rest.splitn(3, |&b| b == 0);
+        let (Some(language_tag), Some(translated_keyword), Some(text)) =
+            (fields.next(), fields.next(), fields.next())
+        else {
+            return Err(invalid(
+                "translated keyword",
+                "is not followed by a null separator",
+            ));
+        };
+        let text = if compressed {
+            inflate(text)?
+        } else {
+            text.to_vec()
+        };
+
+        Self::new(
+            &keyword,
+            compressed,
+            utf8(language_tag, "language tag")?,
+            utf8(translated_keyword, "translated keyword")?,
+            utf8(&text, "text")?,
+        )
+    }
+}
+
+impl TryFrom<&Chunk> for InternationalTextChunk {
+    type Error = ProgramError;
+
+    fn try_from(chunk: &Chunk) -> ProgramResult<Self> {
+        Self::try_from(&chunk.as_chunk_ref())
+    }
+}
+
+impl core::fmt::Display for InternationalTextChunk {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.keyword)?;
+        if !self.language_tag.is_empty() {
+            write!(f, " [{}]", self.language_tag)?;
+        }
+        if !self.translated_keyword.is_empty() {
+            write!(f, " ({})", self.translated_keyword)?;
+        }
+        write!(f, ": {}", self.text)
+    }
+}
+
+/// A textual metadata entry, stored in any of the textual chunk types.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TextEntry {
+    /// An uncompressed `tEXt` entry
+    Text(TextChunk),
+    /// A compressed `zTXt` entry
+    Compressed(CompressedTextChunk),
+    /// A UTF-8 `iTXt` entry
+    International(InternationalTextChunk),
+}
+
+impl TextEntry {
+    /// The keyword naming this entry, such as "Title" or "Author"
+    pub fn keyword(&self) -> &str {
+        match self {
+            Self::Text(t) => t.keyword(),
+            Self::Compressed(t) => t.keyword(),
+            Self::International(t) => t.keyword(),
+        }
+    }
+
+    /// The text of this entry, decompressed if need be
+    pub fn text(&self) -> &str {
+        match self {
+            Self::Text(t) => t.text(),
+            Self::Compressed(t) => t.text(),
+            Self::International(t) => t.text(),
+        }
+    }
+
+    /// Encodes this entry as a chunk of the matching type.
+    pub fn to_chunk(&self) -> ProgramResult<Chunk> {
+        match self {
+            Self::Text(t) => Ok(t.to_chunk()),
+            Self::Compressed(t) => t.to_chunk(),
+            Self::International(t) => t.to_chunk(),
+        }
+    }
+}
+
+impl From<TextChunk> for TextEntry {
+    fn from(entry: TextChunk) -> Self {
+        Self::Text(entry)
+    }
+}
+
+impl From<CompressedTextChunk> for TextEntry {
+    fn from(entry: CompressedTextChunk) -> Self {
+        Self::Compressed(entry)
+    }
+}
+
+impl From<InternationalTextChunk> for TextEntry {
+    fn from(entry: InternationalTextChunk) -> Self {
+        Self::International(entry)
+    }
+}
+
+impl TryFrom<&ChunkRef<'_>> for TextEntry {
+    type Error = ProgramError;
+
+    fn try_from(chunk: &ChunkRef<'_>) -> ProgramResult<Self> {
+        match chunk.chunk_type().to_string().as_str() {
+            TextChunk::CHUNK_TYPE => TextChunk::try_from(chunk).map(Self::Text),
+            CompressedTextChunk::CHUNK_TYPE => {
+                CompressedTextChunk::try_from(chunk).map(Self::Compressed)
+            }
+            InternationalTextChunk::CHUNK_TYPE => {
+                InternationalTextChunk::try_from(chunk).map(Self::International)
+            }
+            other => Err(invalid(
+                "type",
+                format!("{} is not a textual chunk type", other),
+            )),
+        }
+    }
+}
+
+impl TryFrom<&Chunk> for TextEntry {
+    type Error = ProgramError;
+
+    fn try_from(chunk: &Chunk) -> ProgramResult<Self> {
+        Self::try_from(&chunk.as_chunk_ref())
+    }
+}
+
+impl core::fmt::Display for TextEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Text(t) => t.fmt(f),
+            Self::Compressed(t) => t.fmt(f),
+            Self::International(t) => t.fmt(f),
+        }
+    }
+}
+
+/// Returns every valid textual entry of `png`, in file order.
+///
+/// Chunks that break the spec are skipped.
+pub fn list(png: &Png) -> Vec<TextEntry> {
+    png.chunks()
+        .iter()
+        .filter_map(|c| TextEntry::try_from(c).ok())
+        .collect()
+}
+
+/// Returns the first textual entry of `png` named `keyword`, if any.
+pub fn get(png: &Png, keyword: &str) -> Option<TextEntry> {
+    list(png).into_iter().find(|t| t.keyword() == keyword)
+}
+
+/// Stores `entry` in `png`, replacing any entries with the same keyword, whatever
+/// their chunk type.
+///
+/// The new chunk takes the place of the first entry it replaces, or goes right
+/// before `IEND` if there is none.
+pub fn set(png: &mut Png, entry: &TextEntry) -> ProgramResult<()> {
+    let chunk = entry.to_chunk()?;
+    match position_of(png, entry.keyword()) {
+        Some(index) => {
+            png.chunks[index] = chunk;
+            let _ = remove_from(png, entry.keyword(), index + 1);
+            Ok(())
+        }
+        None => png.insert_chunk(chunk, ChunkPosition::BeforeIend),
+    }
+}
+
+/// Removes every entry of `png` named `keyword`, returning how many were removed.
+pub fn delete(png: &mut Png, keyword: &str) -> usize {
+    remove_from(png, keyword, 0)
+}
+
+/// Returns true if `chunk` is a valid textual entry named `keyword`.
+fn is_named(chunk: &Chunk, keyword: &str) -> bool {
+    TextEntry::try_from(chunk).is_ok_and(|t| t.keyword() == keyword)
+}
+
+/// Returns the index of the first entry of `png` named `keyword`.
+fn position_of(png: &Png, keyword: &str) -> Option<usize> {
+    png.chunks.iter().position(|c| is_named(c, keyword))
+}
+
+/// Removes the entries named `keyword` at or after chunk index `start`.
+fn remove_from(png: &mut Png, keyword: &str, start: usize) -> usize {
+    let before = png.chunks.len();
+    let mut index = 0;
+    png.chunks.retain(|c| {
+        index += 1;
+        index <= start || !is_named(c, keyword)
+    });
+    before - png.chunks.len()
+}
+
+/// Fails unless `chunk` is of type `expected`.
+fn check_type(chunk: &ChunkRef<'_>, expected: &str) -> ProgramResult<()> {
+    if chunk.chunk_type().to_string() != expected {
+        return Err(invalid(
+            "type",
+            format!("expected {}, received {}", expected, chunk.chunk_type()),
+        ));
+    }
+    Ok(())
+}
+
+/// Fails unless `method` is `COMPRESSION_ZLIB`, the only method the spec defines.
+pub(crate) fn check_compression_method(method: u8) -> ProgramResult<()> {
+    if method != COMPRESSION_ZLIB {
+        return Err(invalid(
+            "compression method",
+            format!("expected {} (zlib), received {}", COMPRESSION_ZLIB, method),
+        ));
+    }
+    Ok(())
+}
+
+/// Compresses `bytes` into a zlib stream.
+pub(crate) fn deflate(bytes: &[u8]) -> ProgramResult<Vec<u8>> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(bytes)?;
+    Ok(encoder.finish()?)
+}
+
+/// Fails if a text of `len` bytes is too long to be inflated by `inflate`.
+fn check_text_len(len: usize) -> ProgramResult<()> {
+    if len > MAX_TEXT_LEN {
+        return Err(invalid(
+            "text",
+            format!(
+                "must be at most {} bytes long to be compressed, received {}",
+                MAX_TEXT_LEN, len
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Decompresses the zlib stream `zlib`, which must inflate to at most `MAX_TEXT_LEN` bytes.
+pub(crate) fn inflate(zlib: &[u8]) -> ProgramResult<Vec<u8>> {
+    let mut inflated = Vec::new();
+    // Reading one byte past the limit catches zlib bombs without inflating all of them
+    let _ = ZlibDecoder::new(zlib)
+        .take(MAX_TEXT_LEN as u64 + 1)
+        .read_to_end(&mut inflated)
+        .map_err(|e| invalid("text", format!("corrupt zlib stream: {}", e)))?;
+    if inflated.len() > MAX_TEXT_LEN {
+        return Err(invalid(
+            "text",
+            format!("inflates to more than {} bytes", MAX_TEXT_LEN),
+        ));
+    }
+    Ok(inflated)
+}
+
+/// Checks the keyword rules of the PNG spec: 1-79 printable Latin-1 characters,
+/// without leading, trailing or consecutive spaces.
+pub(crate) fn validate_keyword(keyword: &str) -> ProgramResult<()> {
+    let bytes = latin1_encode(keyword, "keyword")?;
+    if bytes.is_empty() || bytes.len() > MAX_KEYWORD_LEN {
+        return Err(invalid(
+            "keyword",
+            format!(
+                "must be 1 to {} bytes long, received {}",
+                MAX_KEYWORD_LEN,
+                bytes.len()
+            ),
+        ));
+    }
+    if let Some(b) = bytes.iter().find(|&&b| !(32..=126).contains(&b) && b < 161) {
+        return Err(invalid(
+            "keyword",
+            format!("must be printable Latin-1, found byte {:#04x}", b),
+        ));
+    }
+    if keyword.starts_with(' ') || keyword.ends_with(' ') || keyword.contains("  ") {
+        return Err(invalid(
+            "keyword",
+            "must not have leading, trailing or consecutive spaces",
+        ));
+    }
+    Ok(())
+}
+
+/// Splits chunk data at the null separator that ends the keyword, checking the keyword.
+///
+/// Returns the keyword and the bytes that follow the separator.
+pub(crate) fn split_keyword(data: &[u8]) -> ProgramResult<(String, &[u8])> {
+    let end = data
+        .iter()
+        .position(|&b| b == 0)
+        .ok_or_else(|| invalid("keyword", "is not followed by a null separator"))?;
+    let keyword = latin1_decode(&data[..end]);
+    validate_keyword(&keyword)?;
+    Ok((keyword, &data[end + 1..]))
+}
+
+/// Checks that `tag` is empty or a language tag as in RFC 3066: hyphen-separated
+/// words of 1-8 ASCII letters or digits.
+pub(crate) fn validate_language_tag(tag: &str) -> ProgramResult<()> {
+    let valid_word =
+        |w: &str| (1..=8).contains(&w.len()) && w.bytes().all(|b| b.is_ascii_alphanumeric());
+    if !tag.is_empty() && !tag.split('-').all(valid_word) {
+        return Err(invalid(
+            "language tag",
+            format!(
+                "{:?} is not made of hyphen-separated words of 1-8 ASCII letters or digits",
+                tag
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Decodes the UTF-8 bytes of `field`.
+fn utf8<'a>(bytes: &'a [u8], field: &'static str) -> ProgramResult<&'a str> {
+    core::str::from_utf8(bytes).map_err(|e| invalid(field, format!("is not valid UTF-8: {}", e)))
+}
+
+/// Encodes `s` as Latin-1 (ISO 8859-1), failing on characters outside it.
+pub(crate) fn latin1_encode(s: &str, field: &'static str) -> ProgramResult<Vec<u8>> {
+    s.chars()
+        .map(|c| {
+            u8::try_from(u32::from(c))
+                .map_err(|_| invalid(field, format!("{:?} is not a Latin-1 character", c)))
+        })
+        .collect()
+}
+
+/// Encodes a string that is known to hold only Latin-1 characters.
+pub(crate) fn latin1_bytes(s: &str) -> impl Iterator<Item = u8> + '_ {
+    s.chars().map(|c| u32::from(c) as u8)
+}
+
+/// Decodes Latin-1 (ISO 8859-1) bytes, which map one-to-one onto the first 256 characters.
+pub(crate) fn latin1_decode(bytes: &[u8]) -> String {
+    bytes.iter().map(|&b| char::from(b)).collect()
+}
+
+/// Builds a `ProgramError::InvalidText` for `field`.
+pub(crate) fn invalid(field: &'static str, reason: impl Into<String>) -> ProgramError {
+    ProgramError::InvalidText {
+        field,
+        reason: reason.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::DICE_PNG;
+
+    #[test]
+    fn test_text_chunk_round_trip() {
+        let text = TextChunk::new("Title", "Café au lait").unwrap();
+        let chunk = text.to_chunk();
+        assert_eq!(chunk.chunk_type().to_string(), "tEXt");
+        assert_eq!(chunk.data(), b"Title\0Caf\xe9 au lait");
+        assert_eq!(TextChunk::try_from(&chunk).unwrap(), text);
+    }
+
+    #[test]
+    fn test_invalid_keywords() {
+        let long = "k".repeat(80);
+        for keyword in [
+            "",
+            " Title",
+            "Title ",
+            "Two  spaces",
+            long.as_str(),
+            "Tab\t",
+        ] {
+            assert!(
+                matches!(
+                    TextChunk::new(keyword, "text"),
+                    Err(ProgramError::InvalidText {
+                        field: "keyword",
+                        ..
+                    })
+                ),
+                "{:?}",
+                keyword
+            );
+        }
+        assert!(TextChunk::new(&"k".repeat(79), "text").is_ok());
+    }
+
+    #[test]
+    fn test_invalid_text() {
+        assert!(matches!(
+            TextChunk::new("Title", "日本"),
+            Err(ProgramError::InvalidText { field: "text", .. })
+        ));
+        assert!(TextChunk::new("Title", "a\0b").is_err());
+
+        let chunk = Chunk::new(ChunkType::from_str("tEXt").unwrap(), b"Title".to_vec());
+        assert!(TextChunk::try_from(&chunk).is_err());
+    }
+
+    #[test]
+    fn test_set_get_delete() {
+        let mut png = Png::try_from(DICE_PNG.as_slice()).unwrap();
+        let chunk_count = png.chunks().len();
+        assert!(list(&png).is_empty());
+
+        set(&mut png, &TextChunk::new("Title", "Dice").unwrap().into()).unwrap();
+        set(
+            &mut png,
+            &TextChunk::new("Author", "Wikipedia").unwrap().into(),
+        )
+        .unwrap();
+        set(
+            &mut png,
+            &TextChunk::new("Title", "Two dice").unwrap().into(),
+        )
+        .unwrap();
+        assert_eq!(png.chunks().len(), chunk_count + 2);
+        assert_eq!(get(&png, "Title").unwrap().text(), "Two dice");
+        assert_eq!(list(&png)[0].keyword(), "Title");
+
+        assert_eq!(delete(&mut png, "Title"), 1);
+        assert_eq!(delete(&mut png, "Title"), 0);
+        assert_eq!(get(&png, "Title"), None);
+        assert_eq!(
+            png.chunks().last().unwrap().chunk_type().to_string(),
+            "IEND"
+        );
+    }
+
+    #[test]
+    fn test_compressed_text_chunk_round_trip() {
+        let text = CompressedTextChunk::new("Comment", &"Café ".repeat(100)).unwrap();
+        let chunk = text.to_chunk().unwrap();
+        assert_eq!(chunk.chunk_type().to_string(), "zTXt");
+        assert!(chunk.data().starts_with(b"Comment\0\0"));
+        assert!(chunk.data().len() < 100);
+        assert_eq!(
+            inflate(&chunk.data()[9..]).unwrap(),
+            b"Caf\xe9 ".repeat(100)
+        );
+        assert_eq!(CompressedTextChunk::try_from(&chunk).unwrap(), text);
+        assert_eq!(
+            TextEntry::try_from(&chunk).unwrap(),
+            TextEntry::Compressed(text)
+        );
+    }
+
+    #[test]
+    fn test_invalid_compressed_text() {
+        let ztxt = |data: Vec<u8>| Chunk::new(ChunkType::from_str("zTXt").unwrap(), data);
+
+        let mut data = b"Title\0\x01".to_vec();
+        data.extend(deflate(b"text").unwrap());
+        assert!(matches!(
+            CompressedTextChunk::try_from(&ztxt(data)),
+            Err(ProgramError::InvalidText {
+                field: "compression method",
+                ..
+            })
+        ));
+        assert!(CompressedTextChunk::try_from(&ztxt(b"Title\0".to_vec())).is_err());
+        assert!(matches!(
+            CompressedTextChunk::try_from(&ztxt(b"Title\0\0not zlib".to_vec())),
+            Err(ProgramError::InvalidText { field: "text", .. })
+        ));
+
+        let long = "a".repeat(MAX_TEXT_LEN + 1);
+        assert!(CompressedTextChunk::new("Title", &long).is_err());
+        let mut bomb = b"Title\0\0".to_vec();
+        bomb.extend(deflate(long.as_bytes()).unwrap());
+        assert!(matches!(
+            CompressedTextChunk::try_from(&ztxt(bomb)),
+            Err(ProgramError::InvalidText { field: "text", .. })
+        ));
+    }
+
+    #[test]
+    fn test_set_replaces_other_chunk_types() {
+        let mut png = Png::try_from(DICE_PNG.as_slice()).unwrap();
+        set(&mut png, &TextChunk::new("Title", "Dice").unwrap().into()).unwrap();
+        let compressed = CompressedTextChunk::new("Title", "Two dice").unwrap();
+        set(&mut png, &compressed.clone().into()).unwrap();
+
+        assert_eq!(list(&png), vec![TextEntry::Compressed(compressed)]);
+        assert_eq!(get(&png, "Title").unwrap().text(), "Two dice");
+        assert_eq!(delete(&mut png, "Title"), 1);
+    }
+
+    #[test]
+    fn test_international_text_chunk_round_trip() {
+        let text =
+            InternationalTextChunk::new("Title", false, "ja", "タイトル", "サイコロ").unwrap();
+        let chunk = text.to_chunk().unwrap();
+        assert_eq!(chunk.chunk_type().to_string(), "iTXt");
+        let expected = [
+            b"Title\0\0\0ja\0".as_slice(),
+            "タイトル\0サイコロ".as_bytes(),
+        ]
+        .concat();
+        assert_eq!(chunk.data(), expected);
+        assert_eq!(InternationalTextChunk::try_from(&chunk).unwrap(), text);
+        assert_eq!(text.to_string(), "Title [ja] (タイトル): サイコロ");
+
+        let compressed =
+            InternationalTextChunk::new("Comment", true, "", "", &"λ".repeat(100)).unwrap();
+        let chunk = compressed.to_chunk().unwrap();
+        assert!(chunk.data().starts_with(b"Comment\0\x01\0\0\0"));
+        assert_eq!(
+            TextEntry::try_from(&chunk).unwrap(),
+            TextEntry::International(compressed)
+        );
+    }
+
+    #[test]
+    fn test_invalid_international_text() {
+        for tag in ["-", "en-", "toolongtag", "en_GB", "日本"] {
+            assert!(
+                matches!(
+                    InternationalTextChunk::new("Title", false, tag, "", "text"),
+                    Err(ProgramError::InvalidText {
+                        field: "language tag",
+                        ..
+                    })
+                ),
+                "{:?}",
+                tag
+            );
+        }
+        assert!(InternationalTextChunk::new("Title", false, "en-GB", "", "text").is_ok());
+        assert!(InternationalTextChunk::new("Title", false, "", "a\0b", "text").is_err());
+
+        let long = "a".repeat(MAX_TEXT_LEN + 1);
+        assert!(InternationalTextChunk::new("Title", true, "", "", &long).is_err());
+        assert!(InternationalTextChunk::new("Title", false, "", "", &long).is_ok());
+
+        let itxt = |data: &[u8]| Chunk::new(ChunkType::from_str("iTXt").unwrap(), data.to_vec());
+        let mut bomb = b"Title\0\x01\0\0\0".to_vec();
+        bomb.extend(deflate(long.as_bytes()).unwrap());
+        for (data, field) in [
+            (b"Title\0\x02\0\0\0text".as_slice(), "compression flag"),
+            (b"Title\0\x01\x08\0\0text", "compression method"),
+            (b"Title\0\0\0en\0", "translated keyword"),
+            (b"Title\0\0\0\0\0\xff", "text"),
+            (&bomb, "text"),
+        ] {
+            assert!(
+                matches!(
+                    InternationalTextChunk::try_from(&itxt(data)),
+                    Err(ProgramError::InvalidText { field: f, .. }) if f == field
+                ),
+                "{:?}",
+                field
+            );
+        }
+    }
+
+    #[test]
+    fn test_list_every_text_chunk_type() {
+        let mut png = Png::try_from(DICE_PNG.as_slice()).unwrap();
+        let entries: Vec<TextEntry> = vec![
+            TextChunk::new("Title", "Dice").unwrap().into(),
+            CompressedTextChunk::new("Comment", "Two dice")
+                .unwrap()
+                .into(),
+            InternationalTextChunk::new("Description", true, "fr", "Description", "Deux dés")
+                .unwrap()
+                .into(),
+        ];
+        for entry in &entries {
+            set(&mut png, entry).unwrap();
+        }
+        assert_eq!(list(&png), entries);
+        assert_eq!(get(&png, "Description").unwrap().text(), "Deux dés");
+    }
+}
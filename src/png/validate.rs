@@ -0,0 +1,374 @@
+//! Implements the chunk ordering and multiplicity rules of the PNG specification.
+//! http://www.libpng.org/pub/png/spec/1.2/PNG-Chunks.html#C.Summary-of-standard-chunks
+
+use super::{header::ColorType, PngRef};
+
+/// Chunks that may appear at most once
+const SINGLE: [&str; 21] = [
+    "IHDR", "PLTE", "IEND", "acTL", "bKGD", "cHRM", "cICP", "cLLi", "eXIf", "gAMA", "hIST", "iCCP",
+    "mDCv", "oFFs", "pCAL", "pHYs", "sBIT", "sCAL", "sRGB", "tIME", "tRNS",
+];
+
+/// Chunks that must appear before `PLTE` (and so before the first `IDAT`)
+const BEFORE_PLTE: [&str; 8] = [
+    "cHRM", "cICP", "cLLi", "gAMA", "iCCP", "mDCv", "sBIT", "sRGB",
+];
+
+/// Chunks that must appear after `PLTE` and before the first `IDAT`
+const AFTER_PLTE: [&str; 3] = ["bKGD", "hIST", "tRNS"];
+
+/// Chunks that must appear before the first `IDAT`
+const BEFORE_IDAT: [&str; 7] = ["acTL", "eXIf", "oFFs", "pCAL", "pHYs", "sCAL", "sPLT"];
+
+/// Critical chunks this crate knows about; any other critical chunk makes a file undecodable
+const KNOWN_CRITICAL: [&str; 4] = ["IHDR", "PLTE", "IDAT", "IEND"];
+
+/// A rule of the PNG specification that a `Png` breaks.
+///
+/// Chunk positions are indices into `Png::chunks`.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum Violation {
+    /// A chunk that every file, or every file of its color type, must have
+    #[error("Missing required {chunk_type} chunk")]
+    MissingChunk {
+        /// The missing chunk type
+        chunk_type: &'static str,
+    },
+
+    /// The first chunk is not `IHDR`
+    #[error("The first chunk is {found}, not IHDR")]
+    IhdrNotFirst {
+        /// Type of the first chunk
+        found: String,
+    },
+
+    /// The `IHDR` chunk holds invalid values
+    #[error("Invalid IHDR chunk: {0}")]
+    InvalidHeader(String),
+
+    /// A second copy of a chunk that may appear only once
+    #[error("Chunk {index} is a second {chunk_type} chunk; only one is allowed")]
+    Duplicate {
+        /// The repeated chunk type
+        chunk_type: String,
+        /// Position of the repeated chunk
+        index: usize,
+    },
+
+    /// An `IDAT` chunk separated from the previous `IDAT` chunk by other chunks
+    #[error("IDAT chunk {index} does not directly follow the previous IDAT chunk")]
+    IdatNotConsecutive {
+        /// Position of the separated chunk
+        index: usize,
+    },
+
+    /// A chunk on the wrong side of `PLTE` or `IDAT`
+    #[error("{chunk_type} chunk {index} must appear {rule}")]
+    Misplaced {
+        /// The misplaced chunk type
+        chunk_type: String,
+        /// Position of the misplaced chunk
+        index: usize,
+        /// Where the chunk belongs, such as "before the first IDAT"
+        rule: &'static str,
+    },
+
+    /// A chunk that is not allowed in this file at all
+    #[error("{chunk_type} chunk {index} is not allowed {reason}")]
+    Forbidden {
+        /// The forbidden chunk type
+        chunk_type: String,
+        /// Position of the forbidden chunk
+        index: usize,
+        /// Why the chunk is not allowed, such as "in grayscale images"
+        reason: &'static str,
+    },
+
+    /// Two chunks that exclude each other
+    #[error("{0} and {1} chunks must not both be present")]
+    Conflicting(&'static str, &'static str),
+
+    /// A critical chunk this crate does not know, which decoders must reject
+    #[error("Unknown critical chunk {chunk_type} at {index}")]
+    UnknownCritical {
+        /// The unknown chunk type
+        chunk_type: String,
+        /// Position of the unknown chunk
+        index: usize,
+    },
+
+    /// A chunk stored after `IEND`
+    #[error("{chunk_type} chunk {index} comes after IEND")]
+    AfterIend {
+        /// The chunk type after `IEND`
+        chunk_type: String,
+        /// Position of the chunk
+        index: usize,
+    },
+
+    /// Bytes stored after `IEND`
+    #[error("{length} bytes of trailing data after IEND")]
+    TrailingData {
+        /// Number of trailing bytes
+        length: usize,
+    },
+}
+
+/// Checks the chunks of `png` against every ordering and multiplicity rule.
+pub(crate) fn violations(png: &PngRef<'_>) -> Vec<Violation> {
+    let types: Vec<String> = png
+        .chunks()
+        .iter()
+        .map(|c| c.chunk_type().to_string())
+        .collect();
+    let first = |chunk_type: &str| types.iter().position(|t| t == chunk_type);
+    let mut violations = Vec::new();
+
+    for required in ["IHDR", "IDAT", "IEND"] {
+        if first(required).is_none() {
+            violations.push(Violation::MissingChunk {
+                chunk_type: required,
+            });
+        }
+    }
+    match types.first() {
+        Some(t) if t != "IHDR" && first("IHDR").is_some() => {
+            violations.push(Violation::IhdrNotFirst { found: t.clone() })
+        }
+        _ => {}
+    }
+    let color_type = match png.header_info() {
+        Ok(header) => Some(header.color_type()),
+        Err(e) if first("IHDR").is_some() => {
+            violations.push(Violation::InvalidHeader(e.to_string()));
+            None
+        }
+        Err(_) => None,
+    };
+
+    let plte = first("PLTE");
+    let idat = first("IDAT");
+    let iend = first("IEND");
+    for (index, chunk_type) in types.iter().enumerate() {
+        let t = chunk_type.as_str();
+        if SINGLE.contains(&t) && first(t) != Some(index) {
+            violations.push(Violation::Duplicate {
+                chunk_type: chunk_type.clone(),
+                index,
+            });
+        }
+        if t == "IDAT" && index > 0 && idat != Some(index) && types[index - 1] != "IDAT" {
+            violations.push(Violation::IdatNotConsecutive { index });
+        }
+
+        let mut misplaced = |rule: &'static str| {
+            violations.push(Violation::Misplaced {
+                chunk_type: chunk_type.clone(),
+                index,
+                rule,
+            })
+        };
+        let after = |anchor: Option<usize>| anchor.is_some_and(|a| index > a);
+        if BEFORE_PLTE.contains(&t) && after(plte) {
+            misplaced("before PLTE");
+        }
+        if AFTER_PLTE.contains(&t) && plte.is_some_and(|p| index < p) {
+            misplaced("after PLTE");
+        }
+        let before_idat = t == "PLTE"
+            || BEFORE_PLTE.contains(&t)
+            || AFTER_PLTE.contains(&t)
+            || BEFORE_IDAT.contains(&t);
+        if before_idat && after(idat) {
+            misplaced("before the first IDAT");
+        }
+
+        if after(iend) {
+            violations.push(Violation::AfterIend {
+                chunk_type: chunk_type.clone(),
+                index,
+            });
+        }
+        if png.chunks()[index].chunk_type().is_critical() && !KNOWN_CRITICAL.contains(&t) {
+            violations.push(Violation::UnknownCritical {
+                chunk_type: chunk_type.clone(),
+                index,
+            });
+        }
+
+        let mut forbidden = |reason: &'static str| {
+            violations.push(Violation::Forbidden {
+                chunk_type: chunk_type.clone(),
+                index,
+                reason,
+            })
+        };
+        match (t, color_type) {
+            ("PLTE", Some(ColorType::Grayscale | ColorType::GrayscaleAlpha)) => {
+                forbidden("in grayscale images")
+            }
+            ("tRNS", Some(ColorType::GrayscaleAlpha | ColorType::Rgba)) => {
+                forbidden("in images with an alpha channel")
+            }
+            ("hIST", _) if plte.is_none() => forbidden("without a PLTE chunk"),
+            _ => {}
+        }
+    }
+
+    if color_type == Some(ColorType::Indexed) && plte.is_none() {
+        violations.push(Violation::MissingChunk { chunk_type: "PLTE" });
+    }
+    if first("iCCP").is_some() && first("sRGB").is_some() {
+        violations.push(Violation::Conflicting("iCCP", "sRGB"));
+    }
+    if !png.trailing_data().is_empty() {
+        violations.push(Violation::TrailingData {
+            length: png.trailing_data().len(),
+        });
+    }
+    violations
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::png::Png;
+    use crate::png::{chunk::Chunk, chunk_type::ChunkType};
+    use crate::utils::DICE_PNG;
+    use core::str::FromStr;
+    use std::convert::TryFrom;
+
+    fn chunk(chunk_type: &str) -> Chunk {
+        let data = match chunk_type {
+            "IHDR" => [0, 0, 0, 1, 0, 0, 0, 1, 8, 3, 0, 0, 0].to_vec(),
+            "PLTE" => [0, 0, 0].to_vec(),
+            _ => Vec::new(),
+        };
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data)
+    }
+
+    fn png(chunk_types: &[&str]) -> Png {
+        Png::from_chunks(chunk_types.iter().map(|t| chunk(t)).collect())
+    }
+
+    #[test]
+    fn test_valid_file() {
+        let dice = Png::try_from(DICE_PNG.as_slice()).unwrap();
+        assert_eq!(
+            dice.validate(),
+            [Violation::UnknownCritical {
+                chunk_type: "RuSt".to_string(),
+                index: 5
+            }]
+        );
+        assert_eq!(
+            png(&["IHDR", "gAMA", "PLTE", "tRNS", "IDAT", "IDAT", "tEXt", "IEND"]).validate(),
+            []
+        );
+    }
+
+    #[test]
+    fn test_missing_chunks() {
+        assert_eq!(
+            png(&["IHDR", "IEND"]).validate(),
+            [
+                Violation::MissingChunk { chunk_type: "IDAT" },
+                Violation::MissingChunk { chunk_type: "PLTE" }
+            ]
+        );
+        assert!(png(&["IDAT", "IEND"])
+            .validate()
+            .contains(&Violation::MissingChunk { chunk_type: "IHDR" }));
+    }
+
+    #[test]
+    fn test_ihdr_not_first() {
+        assert_eq!(
+            png(&["tEXt", "IHDR", "PLTE", "IDAT", "IEND"]).validate(),
+            [Violation::IhdrNotFirst {
+                found: "tEXt".to_string()
+            }]
+        );
+    }
+
+    #[test]
+    fn test_duplicates() {
+        assert_eq!(
+            png(&["IHDR", "IHDR", "PLTE", "IDAT", "IEND"]).validate(),
+            [Violation::Duplicate {
+                chunk_type: "IHDR".to_string(),
+                index: 1
+            }]
+        );
+    }
+
+    #[test]
+    fn test_idat_not_consecutive() {
+        assert_eq!(
+            png(&["IHDR", "PLTE", "IDAT", "tEXt", "IDAT", "IEND"]).validate(),
+            [Violation::IdatNotConsecutive { index: 4 }]
+        );
+    }
+
+    #[test]
+    fn test_misplaced_chunks() {
+        assert_eq!(
+            png(&["IHDR", "IDAT", "PLTE", "IEND"]).validate(),
+            [Violation::Misplaced {
+                chunk_type: "PLTE".to_string(),
+                index: 2,
+                rule: "before the first IDAT"
+            }]
+        );
+        assert_eq!(
+            png(&["IHDR", "tRNS", "PLTE", "gAMA", "IDAT", "IEND"]).validate(),
+            [
+                Violation::Misplaced {
+                    chunk_type: "tRNS".to_string(),
+                    index: 1,
+                    rule: "after PLTE"
+                },
+                Violation::Misplaced {
+                    chunk_type: "gAMA".to_string(),
+                    index: 3,
+                    rule: "before PLTE"
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn test_after_iend() {
+        let mut png = png(&["IHDR", "PLTE", "IDAT", "IEND", "tEXt"]);
+        png.set_trailing_data(b"stray".to_vec());
+        let violations = png.validate();
+        assert_eq!(
+            violations,
+            [
+                Violation::AfterIend {
+                    chunk_type: "tEXt".to_string(),
+                    index: 4
+                },
+                Violation::TrailingData { length: 5 }
+            ]
+        );
+    }
+
+    #[test]
+    fn test_forbidden_and_conflicting_chunks() {
+        assert_eq!(
+            png(&["IHDR", "iCCP", "sRGB", "PLTE", "IDAT", "IEND", "ABCD"]).validate(),
+            [
+                Violation::AfterIend {
+                    chunk_type: "ABCD".to_string(),
+                    index: 6
+                },
+                Violation::UnknownCritical {
+                    chunk_type: "ABCD".to_string(),
+                    index: 6
+                },
+                Violation::Conflicting("iCCP", "sRGB")
+            ]
+        );
+    }
+}
@@ -1,6 +1,9 @@
 //! Implements utility functions and types for processing PNG files
 
+use crate::ProgramResult;
 use crc::{Crc, CRC_32_ISO_HDLC};
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
 
 /// Valid bytes are represented by the characters `A-Z` or `a-z`
 pub fn is_valid_byte(byte: u8) -> bool {
@@ -16,7 +19,68 @@ pub fn is_valid_byte(byte: u8) -> bool {
 /// For the purpose of separating into bytes and ordering, the least significant bit of the 32-bit CRC
 /// is defined to be the coefficient of the `x^31` term.
 pub fn crc_checksum(bytes: &[u8]) -> u32 {
-    Crc::<u32>::new(&CRC_32_ISO_HDLC).checksum(bytes)
+    PNG_CRC.checksum(bytes)
+}
+
+/// The CRC-32 algorithm used by `crc_checksum`, for computing a checksum incrementally
+/// with `Crc::digest`
+pub(crate) static PNG_CRC: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+
+/// Replaces the file at `path` with what `write` writes, without ever leaving it half-written.
+///
+/// `write` fills a temporary file in the same directory, which is synced to disk and then
+/// renamed over `path`; if `write` fails or the process dies first, `path` is untouched.
+/// An existing file keeps its permissions and timestamps. With `backup`, the previous
+/// contents are kept as `<path>.bak`. If `path` is a symlink, the file it points to is
+/// replaced and the link is left in place.
+pub fn write_atomic<T>(
+    path: &Path,
+    backup: bool,
+    write: impl FnOnce(&mut File) -> ProgramResult<T>,
+) -> ProgramResult<T> {
+    let link = path;
+    let resolved = match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_symlink() => Some(fs::canonicalize(path)?),
+        _ => None,
+    };
+    let path = resolved.as_deref().unwrap_or(path);
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let mut temp = tempfile::NamedTempFile::new_in(dir)?;
+    let value = write(temp.as_file_mut())?;
+    temp.as_file().sync_all()?;
+
+    if let Ok(metadata) = fs::metadata(path) {
+        temp.as_file().set_permissions(metadata.permissions())?;
+        filetime::set_file_handle_times(
+            temp.as_file(),
+            Some(filetime::FileTime::from_last_access_time(&metadata)),
+            Some(filetime::FileTime::from_last_modification_time(&metadata)),
+        )?;
+        if backup {
+            let backup_path = backup_path(link);
+            // A hard link keeps the original file as-is; copy where links are not supported
+            let _ = fs::remove_file(&backup_path);
+            if fs::hard_link(path, &backup_path).is_err() {
+                let _ = fs::copy(path, &backup_path)?;
+            }
+        }
+    }
+
+    let _ = temp.persist(path).map_err(|e| e.error)?;
+    // Make the rename itself durable
+    #[cfg(unix)]
+    File::open(dir)?.sync_all()?;
+    Ok(value)
+}
+
+/// The path `write_atomic` keeps the previous contents of `path` at: `<path>.bak`
+pub fn backup_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(".bak");
+    PathBuf::from(name)
 }
 
 /// This is the raw bytes for a shrunken version of the `dice.png` image on Wikipedia
@@ -255,3 +319,70 @@ pub const DICE_PNG: [u8; 4803] = [
     227, 51, 62, 227, 92, 0, 224, 127, 1, 208, 202, 28, 31, 66, 176, 235, 16, 0, 0, 0, 3, 82, 117,
     83, 116, 104, 101, 121, 158, 176, 245, 160, 0, 0, 0, 0, 73, 69, 78, 68, 174, 66, 96, 130,
 ];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn test_write_atomic_replaces_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("image.png");
+        fs::write(&path, b"original").unwrap();
+        let mtime = fs::metadata(&path).unwrap().modified().unwrap();
+
+        write_atomic(&path, true, |file| Ok(file.write_all(b"updated")?)).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"updated");
+        assert_eq!(fs::metadata(&path).unwrap().modified().unwrap(), mtime);
+        assert_eq!(fs::read(backup_path(&path)).unwrap(), b"original");
+    }
+
+    #[test]
+    fn test_write_atomic_keeps_file_on_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("image.png");
+        fs::write(&path, b"original").unwrap();
+
+        let result: ProgramResult<()> = write_atomic(&path, false, |file| {
+            file.write_all(b"partial")?;
+            Err(std::io::Error::from(std::io::ErrorKind::Interrupted).into())
+        });
+        assert!(result.is_err());
+        assert_eq!(fs::read(&path).unwrap(), b"original");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_write_atomic_keeps_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("image.png");
+        fs::write(&path, b"original").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).unwrap();
+
+        write_atomic(&path, false, |file| Ok(file.write_all(b"updated")?)).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o640);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_write_atomic_follows_symlink() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("image.png");
+        let link = dir.path().join("link.png");
+        fs::write(&target, b"original").unwrap();
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+
+        write_atomic(&link, true, |file| Ok(file.write_all(b"updated")?)).unwrap();
+        assert!(fs::symlink_metadata(&link)
+            .unwrap()
+            .file_type()
+            .is_symlink());
+        assert_eq!(fs::read(&target).unwrap(), b"updated");
+        assert_eq!(fs::read(backup_path(&link)).unwrap(), b"original");
+    }
+}